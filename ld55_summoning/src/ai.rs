@@ -0,0 +1,314 @@
+// Computer player move selection
+
+use std::sync::Arc;
+
+use rand::Rng;
+use web_time::Instant;
+
+use crate::gamestate::{gen_split_moves, EvalWeights, GameSnapshot, Position, SplitMove};
+use crate::book::OpeningBook;
+use crate::evaluator::Evaluator;
+use crate::learned::LearnedEval;
+use crate::endgame::EndgameSolver;
+use crate::mcts::{Mcts, MctsBudget, Playout};
+use crate::movegen::MoveGen;
+use crate::multisearch::{MultiSearch, MultiStrategy};
+use crate::search::{sort_candidates, AlphaBeta, SearchLimits, SearchResult, StopFlag, MAX_CANDIDATES, WIN_SCORE};
+
+// MCTS playouts for each ply of a depth limit, when a search is limited by depth rather than time
+const MCTS_ITERATIONS_PER_PLY : u32 = 5000;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,     // mostly random, leaning towards decent moves
+    #[default]
+    Medium,   // the original one-ply greedy bot
+    Hard,     // alpha-beta for two players, multiplayer search otherwise
+    HardMcts, // Monte Carlo tree search
+}
+
+impl Difficulty {
+    pub fn name( &self ) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard => "Hard",
+            Difficulty::HardMcts => "Hard (MCTS)",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Personality {
+    #[default]
+    Balanced,
+    Expander,  // spreads out fast and doesn't mind fighting over space
+    Hoarder,   // keeps to space nobody else can reach and hangs on to power
+    Learned,   // plays by the model from the self-play trainer, if there is one
+}
+
+impl Personality {
+    pub fn name( &self ) -> &'static str {
+        match self {
+            Personality::Balanced => "Balanced",
+            Personality::Expander => "Expander",
+            Personality::Hoarder => "Hoarder",
+            Personality::Learned => "Learned",
+        }
+    }
+
+    pub fn next( &self ) -> Personality {
+        match self {
+            Personality::Balanced => Personality::Expander,
+            Personality::Expander => Personality::Hoarder,
+            Personality::Hoarder => Personality::Learned,
+            Personality::Learned => Personality::Balanced,
+        }
+    }
+
+    // Personalities are nudges away from whatever the base weights are
+    pub fn adjust( &self, base : EvalWeights ) -> EvalWeights {
+        match self {
+            Personality::Balanced | Personality::Learned => base,
+            Personality::Expander => EvalWeights {
+                stack : base.stack * 6 / 5,
+                contested_decay_pct : base.contested_decay_pct * 3 / 2,
+                mobility_pct : base.mobility_pct * 7 / 10,
+                ..base
+            },
+            Personality::Hoarder => EvalWeights {
+                open_decay_pct : (base.open_decay_pct + 5).min( 99 ),
+                contested_decay_pct : base.contested_decay_pct * 3 / 5,
+                mobility_pct : base.mobility_pct * 7 / 5,
+                ..base
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AIPolicy {
+    pub difficulty : Difficulty,
+    pub personality : Personality,
+    pub multi : MultiStrategy,  // how the hard player searches with more than one opponent
+}
+
+impl AIPolicy {
+    // Short names like "hard" or "mcts:hoarder", for the command line tools
+    pub fn parse( text : &str ) -> Option<AIPolicy> {
+        let mut parts = text.split( ':' );
+        let difficulty = match parts.next()?.to_lowercase().as_str() {
+            "easy" => Difficulty::Easy,
+            "medium" => Difficulty::Medium,
+            "hard" => Difficulty::Hard,
+            "mcts" => Difficulty::HardMcts,
+            _ => return None,
+        };
+        let personality = match parts.next().map( |p| p.to_lowercase() ).as_deref() {
+            None | Some("balanced") => Personality::Balanced,
+            Some("expander") => Personality::Expander,
+            Some("hoarder") => Personality::Hoarder,
+            Some("learned") => Personality::Learned,
+            _ => return None,
+        };
+        Some( AIPolicy { difficulty, personality, ..Default::default() } )
+    }
+
+    // The reverse of parse
+    pub fn label( &self ) -> String {
+        let difficulty = match self.difficulty {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::HardMcts => "mcts",
+        };
+        match self.personality {
+            Personality::Balanced => difficulty.to_string(),
+            personality => format!( "{}:{}", difficulty, personality.name().to_lowercase() ),
+        }
+    }
+}
+
+// The search engines hang on to tables and trees between turns, so they are
+// kept around rather than made fresh for every move
+pub struct AIEngines {
+    pub search : AlphaBeta,
+    pub multi : MultiSearch,
+    pub mcts : Mcts,
+    pub endgame : EndgameSolver,
+    pub stop : StopFlag,  // shared by all of them
+    pub base_weights : EvalWeights,  // personalities are applied on top of these
+    pub book : Option<Arc<OpeningBook>>,
+    pub model : Option<Arc<LearnedEval>>,  // for the learned personality
+}
+
+impl Default for AIEngines {
+    fn default() -> Self {
+        AIEngines::with_seed( rand::thread_rng().gen() )
+    }
+}
+
+impl AIEngines {
+    // The seed only matters for MCTS, the other engines are deterministic
+    pub fn with_seed( seed : u64 ) -> AIEngines {
+        let mut engines = AIEngines {
+            search : AlphaBeta::default(),
+            multi : MultiSearch::default(),
+            mcts : Mcts::new( Playout::default(), seed ),
+            endgame : EndgameSolver::default(),
+            stop : StopFlag::default(),
+            base_weights : EvalWeights::default(),
+            book : None,
+            model : None,
+        };
+        engines.set_stop( StopFlag::default() );
+        engines
+    }
+
+    // Share one flag between all the engines, so stopping it stops whichever is running
+    pub fn set_stop( &mut self, stop : StopFlag ) {
+        self.search.stop = stop.clone();
+        self.multi.stop = stop.clone();
+        self.mcts.stop = stop.clone();
+        self.endgame.stop = stop.clone();
+        self.stop = stop;
+    }
+
+    pub fn clear( &mut self ) {
+        self.search.clear();
+        self.mcts.clear();
+        self.endgame.clear();
+    }
+
+    pub fn set_move_gen( &mut self, move_gen : MoveGen ) {
+        if move_gen != self.search.move_gen {
+            // the tables and tree were filled in with the other move list
+            self.clear();
+        }
+        self.search.move_gen = move_gen;
+        self.multi.move_gen = move_gen;
+        self.mcts.move_gen = move_gen;
+    }
+
+    pub fn choose_move( &mut self, policy : AIPolicy, pos : &Position, limits : SearchLimits, rng : &mut impl Rng ) -> SearchResult
+    {
+        let evaluator : Arc<dyn Evaluator> = match (&self.model, policy.personality) {
+            (Some(model), Personality::Learned) => model.clone(),
+            _ => Arc::new( policy.personality.adjust( self.base_weights ) ),
+        };
+        let player = pos.to_move;
+        let player_count = pos.seat_count();
+
+        // Everyone but the easy player goes by the book while it has something to say
+        if policy.difficulty != Difficulty::Easy {
+            if let Some(book) = &self.book {
+                if let Some(mv) = book.lookup( pos, rng ) {
+                    let mut candidates = book.moves( pos ).into_iter().map( |(mv, book_move)| (mv, book_move.points as i32) ).collect();
+                    sort_candidates( &mut candidates );
+                    return SearchResult { best : Some( mv ), candidates, ..Default::default() };
+                }
+            }
+        }
+
+        // The hard players play the end of the game perfectly if it can be solved in
+        // half the time, whatever is left goes on searching as usual if not
+        let mut limits = limits;
+        if matches!( policy.difficulty, Difficulty::Hard | Difficulty::HardMcts ) {
+            let started = Instant::now();
+            let solved = self.endgame.solve( pos, limits.time_budget.map( |budget| budget / 2 ) );
+            limits.time_budget = limits.time_budget.map( |budget| budget.saturating_sub( started.elapsed() ) );
+            if let Some(solved) = solved {
+                return SearchResult {
+                    best : Some( solved.best ),
+                    score : solved.margin * WIN_SCORE,
+                    nodes : solved.nodes,
+                    proven : true,
+                    candidates : vec![ (solved.best, solved.margin * WIN_SCORE) ],
+                    ..Default::default()
+                };
+            }
+        }
+
+        match policy.difficulty {
+            Difficulty::Easy | Difficulty::Medium => {
+                let ranked = rank_moves( &pos.snapshot, player, player_count, evaluator.as_ref(), rng );
+                let best = if policy.difficulty == Difficulty::Easy {
+                    pick_biased( &ranked, rng )
+                } else {
+                    ranked.first().map( |(_, mv)| *mv )
+                };
+                SearchResult {
+                    best,
+                    candidates : ranked.iter().take( MAX_CANDIDATES ).map( |(strength, mv)| (*mv, *strength) ).collect(),
+                    ..Default::default()
+                }
+            }
+            Difficulty::Hard => {
+                if player_count == 2 {
+                    self.search.set_evaluator( evaluator );
+                    self.search.search( pos, limits )
+                } else {
+                    self.multi.evaluator = evaluator;
+                    self.multi.strategy = policy.multi;
+                    self.multi.search( pos, limits )
+                }
+            }
+            Difficulty::HardMcts => {
+                self.mcts.evaluator = evaluator;
+                // Without a clock the depth limit says how hard to think
+                let budget = match limits.time_budget {
+                    Some(time) => MctsBudget::time( time ),
+                    None => MctsBudget::iterations( limits.max_depth.max( 1 ) * MCTS_ITERATIONS_PER_PLY ),
+                };
+                self.mcts.search( pos, budget )
+            }
+        }
+    }
+}
+
+// How far ahead of everyone else combined `player` would be after each move, best first
+fn rank_moves( snapshot : &GameSnapshot, player : usize, player_count : i32, evaluator : &dyn Evaluator, rng : &mut impl Rng ) -> Vec<(i32, SplitMove)>
+{
+    let mut ranked : Vec<(i32, SplitMove)> = gen_split_moves( snapshot, player ).into_iter().map( |mv| {
+        let mut next = *snapshot;
+        next.apply_move( mv );
+
+        // a bit of noise so it doesn't always play the same game
+        let player_evals = evaluator.evaluate( &next );
+        let mut strength : i32 = rng.gen_range( 0..1000 );
+        for (other, eval) in player_evals.iter().enumerate() {
+            if other == player {
+                strength += eval * (player_count - 1);
+            } else {
+                strength -= eval;
+            }
+        }
+        (strength, mv)
+    }).collect();
+
+    ranked.sort_by_key( |(strength, _)| std::cmp::Reverse( *strength ) );
+    ranked
+}
+
+// The original one-ply bot: take the move that leaves us furthest ahead of everyone else
+pub fn greedy_move( snapshot : &GameSnapshot, player : usize, player_count : i32, evaluator : &dyn Evaluator, rng : &mut impl Rng ) -> Option<SplitMove>
+{
+    rank_moves( snapshot, player, player_count, evaluator, rng ).first().map( |(_, mv)| *mv )
+}
+
+// Pick any move, but squaring the roll pulls the choice towards the top of the ranking
+pub fn biased_random_move( snapshot : &GameSnapshot, player : usize, player_count : i32, evaluator : &dyn Evaluator, rng : &mut impl Rng ) -> Option<SplitMove>
+{
+    let ranked = rank_moves( snapshot, player, player_count, evaluator, rng );
+    pick_biased( &ranked, rng )
+}
+
+fn pick_biased( ranked : &[(i32, SplitMove)], rng : &mut impl Rng ) -> Option<SplitMove>
+{
+    if ranked.is_empty() {
+        return None;
+    }
+    let roll : f32 = rng.gen();
+    let pick = ((roll * roll) * ranked.len() as f32) as usize;
+    Some( ranked[ pick.min( ranked.len() - 1 ) ].1 )
+}
@@ -0,0 +1,154 @@
+// Post-game review.
+//
+// Every move of a finished game is searched again, deeper than the in-game AI
+// gets to, once for the best move and once with only the move that was played.
+// The difference between the two scores is how much the move gave away, and big
+// enough losses are flagged as mistakes or blunders. Near the end of the game the
+// endgame solver takes over and the scores are proven final margins.
+
+use crate::endgame::EndgameSolver;
+use crate::gamestate::{Position, SplitMove};
+use crate::multisearch::MultiSearch;
+use crate::search::{AlphaBeta, SearchLimits, SearchResult, WIN_SCORE};
+
+// Review scores are in evaluate_position terms, where this is roughly one stack
+pub const STACK_SCORE : i32 = 10_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Judgement {
+    Good,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    pub fn name( &self ) -> &'static str {
+        match self {
+            Judgement::Good => "Good",
+            Judgement::Mistake => "Mistake",
+            Judgement::Blunder => "Blunder",
+        }
+    }
+}
+
+// How much of the score a move can give away before it gets flagged
+#[derive(Copy, Clone, Debug)]
+pub struct Thresholds {
+    pub mistake : i32,
+    pub blunder : i32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds { mistake : 20_000, blunder : 50_000 }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MoveReview {
+    pub before : Position,        // with to_move set to the seat that played
+    pub played : SplitMove,
+    pub played_score : i32,       // both scores are from the mover's point of view
+    pub best : Option<SplitMove>,
+    pub best_score : i32,
+    pub judgement : Judgement,
+    pub proven : bool,            // scored by the endgame solver rather than a search
+}
+
+impl MoveReview {
+    pub fn seat( &self ) -> usize {
+        self.before.to_move
+    }
+
+    pub fn loss( &self ) -> i32 {
+        (self.best_score - self.played_score).max( 0 )
+    }
+
+    // Something better was found, as opposed to the played move just scoring a bit lower
+    pub fn better_move( &self ) -> Option<SplitMove> {
+        self.best.filter( |best| *best != self.played && self.loss() > 0 )
+    }
+}
+
+// A fixed depth, so the scores for the best move and the played move can be compared
+pub fn review_depth( seat_count : i32 ) -> u32 {
+    if seat_count == 2 { 4 } else { 3 }
+}
+
+// Searches score finished games as margin * WIN_SCORE, bring those down to stacks
+fn review_score( score : i32 ) -> i32 {
+    if score.abs() >= WIN_SCORE {
+        score / WIN_SCORE * STACK_SCORE
+    } else {
+        score
+    }
+}
+
+#[derive(Default)]
+pub struct Reviewer {
+    pub thresholds : Thresholds,
+    search : AlphaBeta,
+    multi : MultiSearch,
+    endgame : EndgameSolver,
+}
+
+impl Reviewer {
+    pub fn review_move( &mut self, pos : &Position, played : SplitMove ) -> MoveReview {
+        let mut review = self.solve_move( pos, played ).unwrap_or_else( || self.search_move( pos, played ) );
+        review.best_score = review.best_score.max( review.played_score );
+        review.judgement = if review.loss() >= self.thresholds.blunder {
+            Judgement::Blunder
+        } else if review.loss() >= self.thresholds.mistake {
+            Judgement::Mistake
+        } else {
+            Judgement::Good
+        };
+        review
+    }
+
+    fn solve_move( &mut self, pos : &Position, played : SplitMove ) -> Option<MoveReview> {
+        let best = self.endgame.solve( pos, None )?;
+        let played_result = self.endgame.solve_moves( pos, &[ played ], None )?;
+        Some( MoveReview {
+            before : *pos,
+            played,
+            played_score : played_result.margin * STACK_SCORE,
+            best : Some( best.best ),
+            best_score : best.margin * STACK_SCORE,
+            judgement : Judgement::Good,
+            proven : true,
+        })
+    }
+
+    fn search_move( &mut self, pos : &Position, played : SplitMove ) -> MoveReview {
+        let limits = SearchLimits::depth( review_depth( pos.seat_count() ) );
+        let (best, played_result) : (SearchResult, SearchResult) = if pos.seat_count() == 2 {
+            (self.search.search( pos, limits ), self.search.search_moves( pos, vec![ played ], limits ))
+        } else {
+            (self.multi.search( pos, limits ), self.multi.search_moves( pos, vec![ played ], limits ))
+        };
+        MoveReview {
+            before : *pos,
+            played,
+            played_score : review_score( played_result.score ),
+            best : best.best,
+            best_score : review_score( best.score ),
+            judgement : Judgement::Good,
+            proven : false,
+        }
+    }
+
+    // Review a whole game. `moves` are the seats and moves in the order they were
+    // played, `progress` is told how many moves are done so far.
+    pub fn review_game( &mut self, start : Position, moves : &[(usize, SplitMove)], progress : &mut impl FnMut( usize ) ) -> Vec<MoveReview> {
+        let mut pos = start;
+        let mut reviews = Vec::new();
+        for (seat, mv) in moves {
+            pos.to_move = *seat;
+            reviews.push( self.review_move( &pos, *mv ) );
+            pos.snapshot.apply_move( *mv );
+            progress( reviews.len() );
+        }
+        reviews
+    }
+}
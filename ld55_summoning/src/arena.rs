@@ -0,0 +1,151 @@
+// Headless games between computer players, for the tournament and tuning tools
+
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::ai::{AIEngines, AIPolicy, Personality};
+use crate::book::OpeningBook;
+use crate::gamestate::{EvalWeights, GameSnapshot, Position, SplitMove};
+use crate::learned::LearnedEval;
+use crate::movegen::MoveGen;
+use crate::multisearch::MultiStrategy;
+use crate::search::SearchLimits;
+
+// A computer player along with the base weights its personality is applied to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Entrant {
+    pub policy : AIPolicy,
+    pub weights : EvalWeights,
+    pub model : Option<LearnedEval>,  // for the learned personality
+    pub move_gen : MoveGen,
+    pub use_book : bool,  // play from the opening book, if the game has one
+}
+
+impl From<AIPolicy> for Entrant {
+    fn from( policy : AIPolicy ) -> Self {
+        Entrant {
+            policy,
+            weights : EvalWeights::default(),
+            model : None,
+            move_gen : MoveGen::default(),
+            use_book : false,
+        }
+    }
+}
+
+impl Entrant {
+    // A policy name, then optionally /reduced or /bucketed to search with fewer moves and
+    // /max-n, /paranoid or /best-reply for the multiplayer search, +book to use the
+    // opening book, and @ with a weights file: "hard/paranoid/reduced+book@tuned.txt".
+    // The learned personality takes a model file from the trainer instead: "hard:learned@model.txt"
+    pub fn parse( text : &str ) -> Result<Entrant, String> {
+        let (policy_text, weights_path) = match text.split_once( '@' ) {
+            Some((policy, path)) => (policy, Some( path )),
+            None => (text, None),
+        };
+        let (policy_text, use_book) = match policy_text.strip_suffix( "+book" ) {
+            Some(policy) => (policy, true),
+            None => (policy_text, false),
+        };
+        let mut options = policy_text.split( '/' );
+        let policy_text = options.next().unwrap_or( "" );
+        let policy = AIPolicy::parse( policy_text ).ok_or_else( || format!( "unknown policy '{}'", policy_text ) )?;
+        let mut entrant = Entrant { policy, use_book, ..Entrant::from( policy ) };
+        for option in options {
+            match option {
+                "reduced" => entrant.move_gen = MoveGen::Reduced,
+                "bucketed" => entrant.move_gen = MoveGen::Bucketed,
+                option => entrant.policy.multi = MultiStrategy::parse( option ).ok_or_else( || format!( "unknown option '/{}'", option ) )?,
+            }
+        }
+        match weights_path {
+            Some(path) if policy.personality == Personality::Learned => entrant.model = Some( LearnedEval::load( path )? ),
+            Some(path) => entrant.weights = EvalWeights::load( path )?,
+            None => {}
+        }
+        Ok( entrant )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameRecord {
+    pub start : GameSnapshot,
+    pub seats : u8,
+    pub moves : Vec<(usize, SplitMove)>,  // seat, move
+    pub scores : [i32; 4],
+}
+
+impl GameRecord {
+    // Finishing position of each seat, 0 is first. Tied seats share the better place.
+    pub fn ranks( &self ) -> [usize; 4] {
+        let mut ranks = [0; 4];
+        for (seat, rank) in ranks.iter_mut().enumerate() {
+            *rank = (0..4).filter( |other| {
+                self.seats & (1 << other) != 0 && self.scores[*other] > self.scores[seat]
+            }).count();
+        }
+        ranks
+    }
+}
+
+// Play one game to the end. Empty seats are left out of the game.
+pub fn play_game( start : GameSnapshot, entrants : [Option<Entrant>; 4], limits : SearchLimits, rng : &mut impl Rng ) -> GameRecord
+{
+    play_out( start, &[], entrants, None, limits, rng )
+}
+
+// The same, but with the first few moves already decided, and a book for the
+// entrants that want one
+pub fn play_out( start : GameSnapshot, opening : &[SplitMove], entrants : [Option<Entrant>; 4], book : Option<&Arc<OpeningBook>>, limits : SearchLimits, rng : &mut impl Rng ) -> GameRecord
+{
+    let mut seats = 0;
+    for (seat, entrant) in entrants.iter().enumerate() {
+        if entrant.is_some() {
+            seats |= 1 << seat;
+        }
+    }
+
+    let mut engines : Vec<AIEngines> = entrants.iter().map( |entrant| {
+        let mut engines = AIEngines::with_seed( rng.gen() );
+        if let Some(entrant) = entrant {
+            engines.base_weights = entrant.weights;
+            engines.model = entrant.model.map( Arc::new );
+            engines.set_move_gen( entrant.move_gen );
+            if entrant.use_book {
+                engines.book = book.cloned();
+            }
+        }
+        engines
+    }).collect();
+
+    // Seat 0 might not be playing, or might have nothing to do
+    let mut pos = Position::new( start, 3, seats );
+    pos.advance();
+
+    let mut moves = Vec::new();
+    while !pos.is_game_over() {
+        let seat = pos.to_move;
+        let mv = match opening.get( moves.len() ) {
+            Some(mv) => *mv,
+            None => {
+                let result = engines[seat].choose_move( entrants[seat].unwrap().policy, &pos, limits, rng );
+                let Some(mv) = result.best else {
+                    break;
+                };
+                mv
+            }
+        };
+        moves.push( (seat, mv) );
+        pos = pos.play( mv );
+    }
+
+    let mut scores = [0; 4];
+    for (seat, score) in scores.iter_mut().enumerate() {
+        if seats & (1 << seat) != 0 {
+            *score = pos.snapshot.calc_simple_score( seat as i32 );
+        }
+    }
+
+    GameRecord { start, seats, moves, scores }
+}
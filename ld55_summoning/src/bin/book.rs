@@ -0,0 +1,176 @@
+//! Builds an opening book from self-play.
+//!
+//! Plays games between copies of one computer player on fresh random maps. The
+//! first few moves of every game are picked at random, leaning towards moves that
+//! look decent, so the book gets to see some variety; the rest of the game is left
+//! to the computer player. Each of the first --plies moves is then counted in the
+//! book along with how the game went for whoever played it. The game and the
+//! tournament runner (with `+book`) read the result.
+//!
+//!     book [options] [<policy>]
+//!
+//!   <policy>         who plays the games, written as for the tournament runner (default hard)
+//!   --games N        games to play (default 500)
+//!   --seats N        players per game, 2 to 4 (default 2)
+//!   --plies N        moves from the start of each game to count (default 6)
+//!   --explore N      how many of those are picked at random (default 2)
+//!   --min-games N    leave out moves played fewer times than this (default 3)
+//!   --merge FILE     add to an existing book instead of starting afresh
+//!   --seed N         first map seed (default 1)
+//!   --threads N      games to run at once (default: one per core)
+//!   --depth N        search depth limit when the policy searches
+//!   --time-ms N      thinking time per move when the policy searches (default 50)
+//!   --out FILE       where to write the book (default opening_book.txt)
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use web_time::Duration;
+
+use ld55_summoning::ai::{biased_random_move, AIPolicy, Difficulty};
+use ld55_summoning::arena::{play_out, Entrant, GameRecord};
+use ld55_summoning::book::OpeningBook;
+use ld55_summoning::gamestate::{generate_map, splitmix64, EvalWeights, Position};
+use ld55_summoning::search::SearchLimits;
+
+struct Options {
+    entrant : Entrant,
+    games : u64,
+    seats : u32,
+    plies : usize,
+    explore : usize,
+    min_games : u32,
+    merge : Option<String>,
+    seed : u64,
+    threads : usize,
+    limits : SearchLimits,
+    out : String,
+}
+
+fn usage() -> ! {
+    eprintln!( "usage: book [--games N] [--seats N] [--plies N] [--explore N] [--min-games N] [--merge FILE]" );
+    eprintln!( "            [--seed N] [--threads N] [--depth N] [--time-ms N] [--out FILE] [<policy>]" );
+    std::process::exit( 1 );
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        entrant : Entrant::from( AIPolicy { difficulty : Difficulty::Hard, ..Default::default() } ),
+        games : 500,
+        seats : 2,
+        plies : 6,
+        explore : 2,
+        min_games : 3,
+        merge : None,
+        seed : 1,
+        threads : std::thread::available_parallelism().map( |n| n.get() ).unwrap_or( 1 ),
+        limits : SearchLimits::time( Duration::from_millis( 50 ) ),
+        out : String::from( "opening_book.txt" ),
+    };
+
+    let mut args = std::env::args().skip( 1 );
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else( || usage() );
+        match arg.as_str() {
+            "--games" => opts.games = value().parse().unwrap_or_else( |_| usage() ),
+            "--seats" => opts.seats = value().parse().unwrap_or_else( |_| usage() ),
+            "--plies" => opts.plies = value().parse().unwrap_or_else( |_| usage() ),
+            "--explore" => opts.explore = value().parse().unwrap_or_else( |_| usage() ),
+            "--min-games" => opts.min_games = value().parse().unwrap_or_else( |_| usage() ),
+            "--merge" => opts.merge = Some( value() ),
+            "--seed" => opts.seed = value().parse().unwrap_or_else( |_| usage() ),
+            "--threads" => opts.threads = value().parse().unwrap_or_else( |_| usage() ),
+            "--depth" => {
+                opts.limits.max_depth = value().parse().unwrap_or_else( |_| usage() );
+                opts.limits.time_budget = None;
+            }
+            "--time-ms" => opts.limits.time_budget = Some( Duration::from_millis( value().parse().unwrap_or_else( |_| usage() ) ) ),
+            "--out" => opts.out = value(),
+            "--help" | "-h" => usage(),
+            policy => opts.entrant = Entrant::parse( policy ).unwrap_or_else( |err| {
+                eprintln!( "{}", err );
+                usage();
+            }),
+        }
+    }
+
+    if !(2..=4).contains( &opts.seats ) || opts.threads == 0 {
+        usage();
+    }
+    opts
+}
+
+// 2 for a win, 1 for sharing first place
+fn points( record : &GameRecord ) -> [u32; 4] {
+    let ranks = record.ranks();
+    let winners = (0..4).filter( |seat| record.seats & (1 << seat) != 0 && ranks[*seat] == 0 ).count();
+    ranks.map( |rank| if rank > 0 { 0 } else if winners > 1 { 1 } else { 2 } )
+}
+
+fn play_one( opts : &Options, map_seed : u64, book : &Mutex<OpeningBook> ) {
+    let seats = (1u8 << opts.seats) - 1;
+    let start = generate_map( seats, &mut StdRng::seed_from_u64( map_seed ) );
+    let mut rng = StdRng::seed_from_u64( splitmix64( map_seed ) );
+
+    // Start the game off at random
+    let mut pos = Position::new( start, 3, seats );
+    pos.advance();
+    let mut opening = Vec::new();
+    while opening.len() < opts.explore && !pos.is_game_over() {
+        let Some(mv) = biased_random_move( &pos.snapshot, pos.to_move, pos.seat_count(), &EvalWeights::default(), &mut rng ) else { break };
+        opening.push( mv );
+        pos = pos.play( mv );
+    }
+
+    let entrants = std::array::from_fn( |seat| if seats & (1 << seat) != 0 { Some( opts.entrant ) } else { None } );
+    let record = play_out( start, &opening, entrants, None, opts.limits, &mut rng );
+    let points = points( &record );
+
+    let mut pos = Position::new( start, 3, seats );
+    pos.advance();
+    let mut book = book.lock().unwrap();
+    for (seat, mv) in record.moves.iter().take( opts.plies ) {
+        pos.to_move = *seat;
+        book.record( &pos, *mv, points[*seat] );
+        pos = pos.play( *mv );
+    }
+}
+
+fn main() {
+    let opts = parse_args();
+    let book = Mutex::new( match &opts.merge {
+        Some(path) => OpeningBook::load( path ).unwrap_or_else( |err| {
+            eprintln!( "{}", err );
+            std::process::exit( 1 );
+        }),
+        None => OpeningBook::default(),
+    });
+
+    let next_game = AtomicUsize::new( 0 );
+    std::thread::scope( |scope| {
+        for _ in 0..opts.threads.min( opts.games as usize ) {
+            scope.spawn( || loop {
+                let game = next_game.fetch_add( 1, Ordering::Relaxed ) as u64;
+                if game >= opts.games {
+                    break;
+                }
+                play_one( &opts, opts.seed + game, &book );
+                if (game + 1).is_multiple_of( 50 ) {
+                    eprintln!( "{} games played", game + 1 );
+                }
+            });
+        }
+    });
+
+    let mut book = book.into_inner().unwrap();
+    let positions = book.len();
+    book.prune( opts.min_games );
+    println!( "{} positions seen, {} kept with moves played at least {} times", positions, book.len(), opts.min_games );
+
+    match book.save( &opts.out ) {
+        Ok(()) => println!( "wrote {}", opts.out ),
+        Err(err) => eprintln!( "couldn't write {}", err ),
+    }
+}
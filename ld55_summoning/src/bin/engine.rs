@@ -0,0 +1,130 @@
+//! The built-in computer players behind the text engine protocol (see protocol.rs),
+//! as a reference for writing other engines and for trying out the game's
+//! support for them:
+//!
+//!     ld55_summoning --engine "engine --policy mcts"
+//!
+//!     engine [--policy P] [--weights FILE] [--model FILE] [--reduced] [--bucketed] [--multi S]
+//!
+//!   --policy P       which AI to play as (default hard)
+//!   --weights FILE   evaluation weights to use instead of the defaults
+//!   --model FILE     model from the trainer, for a policy with the learned personality
+//!   --reduced        search with the reduced move generator
+//!   --bucketed       search with only a few amounts for each split, not exact but quicker
+//!   --multi S        max-n, paranoid or best-reply, for searching three and four player games
+
+use std::io::BufRead;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+
+use web_time::Duration;
+
+use ld55_summoning::ai::{AIEngines, AIPolicy, Difficulty};
+use ld55_summoning::arena::Entrant;
+use ld55_summoning::gamestate::{EvalWeights, GameSnapshot, Position};
+use ld55_summoning::learned::LearnedEval;
+use ld55_summoning::movegen::MoveGen;
+use ld55_summoning::multisearch::MultiStrategy;
+use ld55_summoning::protocol::{play_move, set_stacks, Command, Reply};
+use ld55_summoning::search::SearchLimits;
+
+fn usage() -> ! {
+    eprintln!( "usage: engine [--policy P] [--weights FILE] [--model FILE] [--reduced] [--bucketed] [--multi S]" );
+    std::process::exit( 1 );
+}
+
+fn parse_args() -> Entrant {
+    let mut entrant = Entrant::from( AIPolicy { difficulty : Difficulty::Hard, ..Default::default() } );
+    let mut args = std::env::args().skip( 1 );
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else( || usage() );
+        match arg.as_str() {
+            "--policy" => entrant.policy = AIPolicy::parse( &value() ).unwrap_or_else( || usage() ),
+            "--weights" => {
+                let path = value();
+                entrant.weights = EvalWeights::load( &path ).unwrap_or_else( |err| {
+                    eprintln!( "{}", err );
+                    std::process::exit( 1 );
+                });
+            }
+            "--model" => {
+                let path = value();
+                entrant.model = Some( LearnedEval::load( &path ).unwrap_or_else( |err| {
+                    eprintln!( "{}", err );
+                    std::process::exit( 1 );
+                }));
+            }
+            "--reduced" => entrant.move_gen = MoveGen::Reduced,
+            "--bucketed" => entrant.move_gen = MoveGen::Bucketed,
+            "--multi" => entrant.policy.multi = MultiStrategy::parse( &value() ).unwrap_or_else( || usage() ),
+            _ => usage(),
+        }
+    }
+    entrant
+}
+
+fn reply( reply : Reply ) {
+    println!( "{}", reply.to_text() );
+}
+
+fn main() {
+    let entrant = parse_args();
+    let mut engines = AIEngines { base_weights : entrant.weights, model : entrant.model.map( Arc::new ), ..Default::default() };
+    engines.set_move_gen( entrant.move_gen );
+
+    // Read on another thread so a stop can get through while searching
+    let stop = engines.stop.clone();
+    let (sender, commands) = channel();
+    std::thread::spawn( move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            let Some(command) = Command::parse( &line ) else { continue };
+            if matches!( command, Command::Stop | Command::Quit ) {
+                stop.stop();
+            }
+            if sender.send( command ).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut rng = rand::thread_rng();
+    let mut layout = GameSnapshot::default();
+    let mut pos = Position::new( layout, 0, 0 );
+    for command in commands {
+        match command {
+            Command::Engine => {
+                reply( Reply::Id( format!( "ld55 {}", entrant.policy.label() ) ) );
+                reply( Reply::EngineOk );
+            }
+            Command::IsReady => reply( Reply::ReadyOk ),
+            Command::NewGame { seats, layout : new_layout } => {
+                layout = *new_layout;
+                pos = Position::new( layout, 0, seats );
+                engines.clear();
+            }
+            Command::Position { to_move, stacks } => {
+                pos.snapshot = layout;
+                set_stacks( &mut pos.snapshot, &stacks );
+                pos.to_move = to_move;
+            }
+            Command::Move { seat, mv } => match play_move( &pos, seat, mv ) {
+                Ok(after) => pos = after,
+                Err(err) => reply( Reply::Info( err ) ),
+            },
+            Command::Go { movetime_ms, depth } => {
+                let limits = match (movetime_ms, depth) {
+                    (Some(ms), depth) => SearchLimits { max_depth : depth.unwrap_or( 64 ), time_budget : Some( Duration::from_millis( ms ) ) },
+                    (None, Some(depth)) => SearchLimits::depth( depth ),
+                    (None, None) => SearchLimits::default(),
+                };
+                engines.stop.reset();
+                let result = engines.choose_move( entrant.policy, &pos, limits, &mut rng );
+                reply( Reply::Info( format!( "depth {} nodes {} score {}", result.depth, result.nodes, result.score ) ) );
+                reply( Reply::BestMove( result.best ) );
+            }
+            Command::Stop => {}
+            Command::Quit => break,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Checks the reduced move generator against the full one.
+//!
+//! Plays random games and, in every position along the way, checks that the
+//! reduced moves are all real moves and that every full move has its stand-in
+//! among them. Once a two player game gets down to a handful of empty hexes it is
+//! solved exactly with both generators, and the results must match.
+//!
+//! The bucketed generator is measured alongside. It isn't exact, so its endgames
+//! solving differently is counted but isn't a problem.
+//!
+//!     movegen_check [--games N] [--seats N] [--empties N] [--seed N] [--greedy]
+//!
+//!   --games N     random games to play (default 50)
+//!   --seats N     players per game, 2 to 4 (default 2)
+//!   --empties N   solve two player games once this few empty hexes are left (default 9)
+//!   --seed N      first map seed (default 1)
+//!   --greedy      play the games with the medium AI instead of at random, which
+//!                 holds on to power for longer
+
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use ld55_summoning::ai::greedy_move;
+use ld55_summoning::gamestate::{gen_split_moves, generate_map, EvalWeights, MapSpaceContents, Position};
+use ld55_summoning::movegen::{gen_bucketed_moves, gen_reduced_moves, representative, MoveGen};
+
+struct Options {
+    games : u64,
+    seats : u32,
+    empties : usize,
+    seed : u64,
+    greedy : bool,
+}
+
+#[derive(Default)]
+struct Stats {
+    positions : u64,
+    full_moves : u64,
+    reduced_moves : u64,
+    bucketed_moves : u64,
+    errors : u64,
+    solved : u64,
+    full_nodes : u64,
+    reduced_nodes : u64,
+    bucketed_nodes : u64,
+    bucketed_misses : u64,  // endgames the bucketed generator solves to a different result
+}
+
+fn usage() -> ! {
+    eprintln!( "usage: movegen_check [--games N] [--seats N] [--empties N] [--seed N] [--greedy]" );
+    std::process::exit( 1 );
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options { games : 50, seats : 2, empties : 9, seed : 1, greedy : false };
+    let mut args = std::env::args().skip( 1 );
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else( || usage() );
+        match arg.as_str() {
+            "--games" => opts.games = value().parse().unwrap_or_else( |_| usage() ),
+            "--seats" => opts.seats = value().parse().unwrap_or_else( |_| usage() ),
+            "--empties" => opts.empties = value().parse().unwrap_or_else( |_| usage() ),
+            "--seed" => opts.seed = value().parse().unwrap_or_else( |_| usage() ),
+            "--greedy" => opts.greedy = true,
+            _ => usage(),
+        }
+    }
+    if !(2..=4).contains( &opts.seats ) {
+        usage();
+    }
+    opts
+}
+
+fn empty_count( pos : &Position ) -> usize {
+    pos.snapshot.map.spaces.iter().filter( |s| s.contents == MapSpaceContents::Playable && s.power == 0 ).count()
+}
+
+// Final stack margin for the seat to move with perfect play, two players only
+fn solve( pos : &Position, move_gen : MoveGen, memo : &mut HashMap<u64, i32>, nodes : &mut u64 ) -> i32 {
+    *nodes += 1;
+    let me = pos.to_move;
+    if pos.is_game_over() {
+        let other = pos.next_seat( me );
+        return pos.snapshot.calc_simple_score( me as i32 ) - pos.snapshot.calc_simple_score( other as i32 );
+    }
+    if let Some(value) = memo.get( &pos.hash() ) {
+        return *value;
+    }
+
+    let mut best = i32::MIN;
+    for mv in move_gen.moves( &pos.snapshot, me ) {
+        let child = pos.play( mv );
+        let value = solve( &child, move_gen, memo, nodes );
+        best = best.max( if child.to_move == me { value } else { -value } );
+    }
+    memo.insert( pos.hash(), best );
+    best
+}
+
+fn check_position( pos : &Position, stats : &mut Stats ) {
+    let full = gen_split_moves( &pos.snapshot, pos.to_move );
+    let reduced = gen_reduced_moves( &pos.snapshot, pos.to_move );
+    stats.positions += 1;
+    stats.full_moves += full.len() as u64;
+    stats.reduced_moves += reduced.len() as u64;
+
+    let bucketed = gen_bucketed_moves( &pos.snapshot, pos.to_move );
+    stats.bucketed_moves += bucketed.len() as u64;
+    for mv in &bucketed {
+        if !full.contains( mv ) {
+            println!( "bucketed move {:?} is not a legal move", mv );
+            stats.errors += 1;
+        }
+    }
+
+    for mv in &reduced {
+        if !full.contains( mv ) {
+            println!( "reduced move {:?} is not a legal move", mv );
+            stats.errors += 1;
+        }
+    }
+    for mv in &full {
+        if !reduced.contains( &representative( &pos.snapshot, *mv ) ) {
+            println!( "full move {:?} has no stand-in", mv );
+            stats.errors += 1;
+        }
+    }
+}
+
+fn main() {
+    let opts = parse_args();
+    let seats = (1u8 << opts.seats) - 1;
+    let mut stats = Stats::default();
+
+    for game in 0..opts.games {
+        let map_seed = opts.seed + game;
+        let mut rng = StdRng::seed_from_u64( map_seed );
+        let mut pos = Position::new( generate_map( seats, &mut rng ), 3, seats );
+        pos.advance();
+
+        let mut solved = opts.seats != 2;
+        while !pos.is_game_over() {
+            check_position( &pos, &mut stats );
+
+            if !solved && empty_count( &pos ) <= opts.empties {
+                solved = true;
+                let full = solve( &pos, MoveGen::Full, &mut HashMap::new(), &mut stats.full_nodes );
+                let reduced = solve( &pos, MoveGen::Reduced, &mut HashMap::new(), &mut stats.reduced_nodes );
+                stats.solved += 1;
+                if full != reduced {
+                    println!( "map {}: full generator solves to {}, reduced to {}", map_seed, full, reduced );
+                    stats.errors += 1;
+                }
+                let bucketed = solve( &pos, MoveGen::Bucketed, &mut HashMap::new(), &mut stats.bucketed_nodes );
+                if bucketed != full {
+                    stats.bucketed_misses += 1;
+                }
+            }
+
+            let mv = if opts.greedy {
+                greedy_move( &pos.snapshot, pos.to_move, pos.seat_count(), &EvalWeights::default(), &mut rng ).unwrap()
+            } else {
+                *gen_split_moves( &pos.snapshot, pos.to_move ).choose( &mut rng ).unwrap()
+            };
+            pos = pos.play( mv );
+        }
+    }
+
+    let per_position = |moves : u64| moves as f64 / stats.positions.max( 1 ) as f64;
+    let share = |moves : u64| 100.0 * moves as f64 / stats.full_moves.max( 1 ) as f64;
+    println!( "{} positions, {:.1} moves on average, {:.1} after reduction ({:.1}%), {:.1} bucketed ({:.1}%)",
+        stats.positions,
+        per_position( stats.full_moves ),
+        per_position( stats.reduced_moves ), share( stats.reduced_moves ),
+        per_position( stats.bucketed_moves ), share( stats.bucketed_moves ) );
+    if stats.solved > 0 {
+        println!( "{} endgames solved, {} nodes with the full generator, {} reduced, {} bucketed",
+            stats.solved, stats.full_nodes, stats.reduced_nodes, stats.bucketed_nodes );
+        println!( "bucketed solved {} of them to a different result", stats.bucketed_misses );
+    }
+    if stats.errors > 0 {
+        println!( "{} problems found", stats.errors );
+        std::process::exit( 1 );
+    }
+    println!( "no problems found" );
+}
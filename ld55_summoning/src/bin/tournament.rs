@@ -0,0 +1,411 @@
+//! Headless AI-vs-AI tournaments, for measuring changes to the computer players.
+//!
+//!     tournament [options] <policy> <policy> [<policy>...]
+//!
+//! Policies are written like `easy`, `medium`, `hard`, `mcts`, optionally with a
+//! personality: `hard:hoarder`, `mcts:expander`. Add `@file` to play with a set of
+//! evaluation weights from the tuner instead of the defaults: `hard@tuned.txt`, or
+//! with the learned personality a model from the trainer: `hard:learned@model.txt`,
+//! `/reduced` to search with the reduced move generator: `hard/reduced`, or
+//! `/bucketed` for the rougher one that only tries a few amounts, `/max-n`,
+//! `/paranoid` or `/best-reply` for how hard searches three and four player games
+//! (best-reply unless told otherwise): `hard/paranoid`, and `+book` to play the
+//! opening from the --book file: `hard+book`.
+//!
+//!   --mode round-robin|gauntlet  round-robin plays every group of entrants, gauntlet
+//!                                plays the first entrant against each of the others
+//!   --seats N        players per game, 2 to 4 (default 2)
+//!   --maps N         maps per pairing (default 10), each played in every seat rotation
+//!   --seed N         first map seed (default 1)
+//!   --threads N      games to run at once (default: one per core)
+//!   --depth N        search depth limit for the search AIs
+//!   --time-ms N      thinking time per move for the search AIs (default 100)
+//!   --book FILE      opening book for the entrants with +book
+//!   --csv FILE       write the standings as CSV, `-` for stdout
+//!   --json FILE      write standings and every game result as JSON, `-` for stdout
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use web_time::Duration;
+
+use ld55_summoning::arena::{play_out, Entrant};
+use ld55_summoning::book::OpeningBook;
+use ld55_summoning::gamestate::{generate_map, splitmix64};
+use ld55_summoning::search::SearchLimits;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    RoundRobin,
+    Gauntlet,
+}
+
+struct Options {
+    entrants : Vec<Entrant>,
+    names : Vec<String>,
+    mode : Mode,
+    seats : usize,
+    maps : u64,
+    seed : u64,
+    threads : usize,
+    limits : SearchLimits,
+    book : Option<Arc<OpeningBook>>,
+    csv : Option<String>,
+    json : Option<String>,
+}
+
+// One game to play: who sits where, on which map
+struct Job {
+    lineup : Vec<usize>,  // entrant index for each seat
+    map_seed : u64,
+}
+
+struct GameResult {
+    lineup : Vec<usize>,
+    map_seed : u64,
+    scores : Vec<i32>,
+    moves : usize,
+}
+
+#[derive(Default, Clone)]
+struct Standing {
+    games : u32,
+    wins : u32,
+    draws : u32,  // tied for first
+    losses : u32,
+    total_score : i64,
+    total_rank : u64,
+    elo : f64,
+}
+
+fn usage() -> ! {
+    eprintln!( "usage: tournament [--mode round-robin|gauntlet] [--seats N] [--maps N] [--seed N] [--threads N]" );
+    eprintln!( "                  [--depth N] [--time-ms N] [--book FILE] [--csv FILE] [--json FILE] <policy> <policy>..." );
+    eprintln!( "policies: easy, medium, hard, mcts, optionally with :balanced, :expander, :hoarder or :learned" );
+    eprintln!( "          then /reduced or /bucketed for fewer moves, /max-n, /paranoid or /best-reply" );
+    eprintln!( "          for the multiplayer search, +book for the opening book" );
+    eprintln!( "          and @file for weights from a file, or the model for :learned" );
+    std::process::exit( 1 );
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        entrants : Vec::new(),
+        names : Vec::new(),
+        mode : Mode::RoundRobin,
+        seats : 2,
+        maps : 10,
+        seed : 1,
+        threads : std::thread::available_parallelism().map( |n| n.get() ).unwrap_or( 1 ),
+        limits : SearchLimits::time( Duration::from_millis( 100 ) ),
+        book : None,
+        csv : None,
+        json : None,
+    };
+
+    let mut args = std::env::args().skip( 1 );
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else( || usage() );
+        match arg.as_str() {
+            "--mode" => opts.mode = match value().as_str() {
+                "round-robin" => Mode::RoundRobin,
+                "gauntlet" => Mode::Gauntlet,
+                _ => usage(),
+            },
+            "--seats" => opts.seats = value().parse().unwrap_or_else( |_| usage() ),
+            "--maps" => opts.maps = value().parse().unwrap_or_else( |_| usage() ),
+            "--seed" => opts.seed = value().parse().unwrap_or_else( |_| usage() ),
+            "--threads" => opts.threads = value().parse().unwrap_or_else( |_| usage() ),
+            "--depth" => {
+                opts.limits.max_depth = value().parse().unwrap_or_else( |_| usage() );
+                opts.limits.time_budget = None;
+            }
+            "--time-ms" => opts.limits.time_budget = Some( Duration::from_millis( value().parse().unwrap_or_else( |_| usage() ) ) ),
+            "--book" => {
+                let path = value();
+                let book = OpeningBook::load( &path ).unwrap_or_else( |err| {
+                    eprintln!( "{}", err );
+                    std::process::exit( 1 );
+                });
+                opts.book = Some( Arc::new( book ) );
+            }
+            "--csv" => opts.csv = Some( value() ),
+            "--json" => opts.json = Some( value() ),
+            "--help" | "-h" => usage(),
+            entrant => match Entrant::parse( entrant ) {
+                Ok(parsed) => {
+                    opts.entrants.push( parsed );
+                    opts.names.push( entrant.to_string() );
+                }
+                Err(err) => {
+                    eprintln!( "{}", err );
+                    usage();
+                }
+            },
+        }
+    }
+
+    if !(2..=4).contains( &opts.seats ) || opts.entrants.len() < 2 || opts.threads == 0 {
+        usage();
+    }
+    if opts.book.is_none() && opts.entrants.iter().any( |e| e.use_book ) {
+        eprintln!( "+book needs an opening book from --book" );
+        usage();
+    }
+    if opts.mode == Mode::RoundRobin && opts.entrants.len() < opts.seats {
+        eprintln!( "round-robin needs at least as many entrants as seats" );
+        usage();
+    }
+    opts
+}
+
+// Every way of picking `k` entrants out of `n`
+fn combinations( n : usize, k : usize ) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![ Vec::new() ];
+    }
+    let mut result = Vec::new();
+    for first in 0..n {
+        for mut rest in combinations( n - first - 1, k - 1 ) {
+            rest.iter_mut().for_each( |r| *r += first + 1 );
+            rest.insert( 0, first );
+            result.push( rest );
+        }
+    }
+    result
+}
+
+fn build_jobs( opts : &Options ) -> Vec<Job> {
+    let groups : Vec<Vec<usize>> = match opts.mode {
+        Mode::RoundRobin => combinations( opts.entrants.len(), opts.seats ),
+        // The first entrant against a table full of each of the others in turn
+        Mode::Gauntlet => (1..opts.entrants.len()).map( |other| {
+            let mut group = vec![ 0 ];
+            group.extend( std::iter::repeat_n( other, opts.seats - 1 ) );
+            group
+        }).collect(),
+    };
+
+    let mut jobs = Vec::new();
+    for group in &groups {
+        for map in 0..opts.maps {
+            // Same maps for every group, and every entrant gets a go in every seat
+            for rotation in 0..opts.seats {
+                let lineup = (0..opts.seats).map( |seat| group[(seat + rotation) % opts.seats] ).collect();
+                jobs.push( Job { lineup, map_seed : opts.seed + map } );
+            }
+        }
+    }
+    jobs
+}
+
+fn run_job( opts : &Options, job : &Job ) -> GameResult {
+    let seats = (1u8 << opts.seats) - 1;
+    let start = generate_map( seats, &mut StdRng::seed_from_u64( job.map_seed ) );
+
+    let mut entrants = [None; 4];
+    for (seat, entrant) in job.lineup.iter().enumerate() {
+        entrants[seat] = Some( opts.entrants[*entrant] );
+    }
+
+    // Different rotations get different dice, the same job always plays out the same way
+    let lineup_key = job.lineup.iter().fold( 0u64, |acc, e| acc * 8 + *e as u64 + 1 );
+    let mut rng = StdRng::seed_from_u64( splitmix64( job.map_seed ^ (lineup_key << 32) ) );
+    let record = play_out( start, &[], entrants, opts.book.as_ref(), opts.limits, &mut rng );
+
+    GameResult {
+        lineup : job.lineup.clone(),
+        map_seed : job.map_seed,
+        scores : record.scores[..opts.seats].to_vec(),
+        moves : record.moves.len(),
+    }
+}
+
+fn run_all( opts : &Options, jobs : &[Job] ) -> Vec<GameResult> {
+    let next_job = AtomicUsize::new( 0 );
+    let results = Mutex::new( Vec::new() );
+
+    std::thread::scope( |scope| {
+        for _ in 0..opts.threads.min( jobs.len() ) {
+            scope.spawn( || loop {
+                let ndx = next_job.fetch_add( 1, Ordering::Relaxed );
+                if ndx >= jobs.len() {
+                    break;
+                }
+                let result = run_job( opts, &jobs[ndx] );
+
+                let mut results = results.lock().unwrap();
+                results.push( (ndx, result) );
+                if results.len() % 50 == 0 || results.len() == jobs.len() {
+                    eprintln!( "{} / {} games", results.len(), jobs.len() );
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key( |(ndx, _)| *ndx );
+    results.into_iter().map( |(_, result)| result ).collect()
+}
+
+// Bradley-Terry ratings from every head to head result inside each game, put on the
+// Elo scale. Everyone gets one virtual draw against an average player so a clean
+// sweep doesn't run off to infinity.
+fn compute_elo( entrant_count : usize, results : &[GameResult] ) -> Vec<f64> {
+    let mut wins = vec![ 0.5; entrant_count ];
+    let mut played = vec![ vec![ 0.0; entrant_count ]; entrant_count ];
+    for result in results {
+        for a in 0..result.lineup.len() {
+            for b in (a + 1)..result.lineup.len() {
+                let (ea, eb) = (result.lineup[a], result.lineup[b]);
+                if ea == eb {
+                    continue;
+                }
+                played[ea][eb] += 1.0;
+                played[eb][ea] += 1.0;
+                if result.scores[a] > result.scores[b] {
+                    wins[ea] += 1.0;
+                } else if result.scores[a] < result.scores[b] {
+                    wins[eb] += 1.0;
+                } else {
+                    wins[ea] += 0.5;
+                    wins[eb] += 0.5;
+                }
+            }
+        }
+    }
+
+    let mut gamma : Vec<f64> = vec![ 1.0; entrant_count ];
+    for _ in 0..500 {
+        let mut next = gamma.clone();
+        for i in 0..entrant_count {
+            // the virtual game against a gamma = 1 opponent
+            let mut denom = 1.0 / (gamma[i] + 1.0);
+            for j in 0..entrant_count {
+                if played[i][j] > 0.0 {
+                    denom += played[i][j] / (gamma[i] + gamma[j]);
+                }
+            }
+            next[i] = wins[i] / denom;
+        }
+        gamma = next;
+    }
+
+    let elo : Vec<f64> = gamma.iter().map( |g| 400.0 * g.log10() ).collect();
+    let mean = elo.iter().sum::<f64>() / entrant_count as f64;
+    elo.iter().map( |e| e - mean + 1500.0 ).collect()
+}
+
+fn standings( opts : &Options, results : &[GameResult] ) -> Vec<Standing> {
+    let mut table = vec![ Standing::default(); opts.entrants.len() ];
+    for result in results {
+        let top = *result.scores.iter().max().unwrap();
+        let top_count = result.scores.iter().filter( |s| **s == top ).count();
+        for (seat, entrant) in result.lineup.iter().enumerate() {
+            let score = result.scores[seat];
+            let rank = result.scores.iter().filter( |s| **s > score ).count();
+            let standing = &mut table[*entrant];
+            standing.games += 1;
+            standing.total_score += score as i64;
+            standing.total_rank += rank as u64;
+            if score < top {
+                standing.losses += 1;
+            } else if top_count > 1 {
+                standing.draws += 1;
+            } else {
+                standing.wins += 1;
+            }
+        }
+    }
+
+    for (standing, elo) in table.iter_mut().zip( compute_elo( opts.entrants.len(), results ) ) {
+        standing.elo = elo;
+    }
+    table
+}
+
+// Entrant names as typed, with a #n on the end if the same one was entered twice
+fn entrant_names( opts : &Options ) -> Vec<String> {
+    opts.names.iter().enumerate().map( |(i, name)| {
+        if opts.names.iter().filter( |n| *n == name ).count() > 1 {
+            format!( "{}#{}", name, i + 1 )
+        } else {
+            name.clone()
+        }
+    }).collect()
+}
+
+fn to_csv( names : &[String], table : &[Standing] ) -> String {
+    let mut out = String::from( "entrant,games,wins,draws,losses,win_rate,avg_score,avg_rank,elo\n" );
+    for (name, s) in names.iter().zip( table ) {
+        let games = s.games.max( 1 ) as f64;
+        let _ = writeln!( out, "{},{},{},{},{},{:.4},{:.3},{:.3},{:.1}",
+            name, s.games, s.wins, s.draws, s.losses,
+            (s.wins as f64 + 0.5 * s.draws as f64) / games,
+            s.total_score as f64 / games,
+            1.0 + s.total_rank as f64 / games,
+            s.elo );
+    }
+    out
+}
+
+fn to_json( opts : &Options, names : &[String], table : &[Standing], results : &[GameResult] ) -> String {
+    let mut out = String::from( "{\n" );
+    let _ = writeln!( out, "  \"seats\": {},", opts.seats );
+    let _ = writeln!( out, "  \"mode\": \"{}\",", if opts.mode == Mode::Gauntlet { "gauntlet" } else { "round-robin" } );
+    out.push_str( "  \"standings\": [\n" );
+    for (i, (name, s)) in names.iter().zip( table ).enumerate() {
+        let games = s.games.max( 1 ) as f64;
+        let _ = write!( out,
+            "    {{\"entrant\": \"{}\", \"games\": {}, \"wins\": {}, \"draws\": {}, \"losses\": {}, \"win_rate\": {:.4}, \"avg_score\": {:.3}, \"elo\": {:.1}}}",
+            name, s.games, s.wins, s.draws, s.losses,
+            (s.wins as f64 + 0.5 * s.draws as f64) / games,
+            s.total_score as f64 / games,
+            s.elo );
+        out.push_str( if i + 1 < table.len() { ",\n" } else { "\n" } );
+    }
+    out.push_str( "  ],\n  \"games\": [\n" );
+    for (i, r) in results.iter().enumerate() {
+        let lineup : Vec<String> = r.lineup.iter().map( |e| format!( "\"{}\"", names[*e] ) ).collect();
+        let scores : Vec<String> = r.scores.iter().map( |s| s.to_string() ).collect();
+        let _ = write!( out, "    {{\"map_seed\": {}, \"seats\": [{}], \"scores\": [{}], \"moves\": {}}}",
+            r.map_seed, lineup.join( ", " ), scores.join( ", " ), r.moves );
+        out.push_str( if i + 1 < results.len() { ",\n" } else { "\n" } );
+    }
+    out.push_str( "  ]\n}\n" );
+    out
+}
+
+fn write_output( path : &str, contents : &str ) {
+    if path == "-" {
+        print!( "{}", contents );
+    } else if let Err(err) = std::fs::write( path, contents ) {
+        eprintln!( "couldn't write {}: {}", path, err );
+    }
+}
+
+fn main() {
+    let opts = parse_args();
+    let jobs = build_jobs( &opts );
+    eprintln!( "{} entrants, {} games on {} threads", opts.entrants.len(), jobs.len(), opts.threads );
+
+    let results = run_all( &opts, &jobs );
+    let table = standings( &opts, &results );
+    let names = entrant_names( &opts );
+
+    println!( "{:<20} {:>6} {:>6} {:>6} {:>6} {:>8} {:>8}", "entrant", "games", "wins", "draws", "losses", "score", "elo" );
+    for (name, s) in names.iter().zip( &table ) {
+        println!( "{:<20} {:>6} {:>6} {:>6} {:>6} {:>8.2} {:>8.1}",
+            name, s.games, s.wins, s.draws, s.losses, s.total_score as f64 / s.games.max( 1 ) as f64, s.elo );
+    }
+
+    if let Some(path) = &opts.csv {
+        write_output( path, &to_csv( &names, &table ) );
+    }
+    if let Some(path) = &opts.json {
+        write_output( path, &to_json( &opts, &names, &table, &results ) );
+    }
+}
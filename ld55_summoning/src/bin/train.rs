@@ -0,0 +1,198 @@
+//! Self-play trainer for the learned evaluator (see learned.rs), using TD(lambda).
+//!
+//! Plays games between copies of the one-ply AI scoring moves with the model being
+//! trained, with some moves picked at random so it gets to see more than one kind
+//! of game, and runs TD(lambda) over every finished game. Games are played a batch
+//! at a time, one per thread, with the model as it was at the start of the batch.
+//! At the end the model plays the hand tuned evaluator and is written out as a
+//! model file, which the game and the tournament runner (`hard:learned@file`) can load.
+//!
+//!     train [options]
+//!
+//!   --games N        self-play games (default 2000)
+//!   --seats N        players per game, 2 to 4 (default 2)
+//!   --alpha X        learning rate (default 0.01)
+//!   --lambda X       how far back each step reaches, 0 to 1 (default 0.7)
+//!   --explore X      chance of a random move instead of the best one (default 0.1)
+//!   --start FILE     model to carry on training instead of starting afresh
+//!   --validate N     maps for the final check against the hand tuned evaluator (default 20)
+//!   --seed N         first map seed (default 1)
+//!   --threads N      games to run at once (default: one per core)
+//!   --out FILE       where to write the model (default ai_model.txt, which the game picks up)
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use ld55_summoning::ai::{biased_random_move, greedy_move, AIPolicy, Difficulty, Personality};
+use ld55_summoning::arena::{play_game, Entrant};
+use ld55_summoning::gamestate::{generate_map, splitmix64, GameSnapshot, Position};
+use ld55_summoning::learned::{LearnedEval, MODEL_FILE};
+use ld55_summoning::search::SearchLimits;
+
+struct Options {
+    games : u64,
+    seats : u32,
+    alpha : f32,
+    lambda : f32,
+    explore : f64,
+    start : LearnedEval,
+    validate : u64,
+    seed : u64,
+    threads : usize,
+    out : String,
+}
+
+fn usage() -> ! {
+    eprintln!( "usage: train [--games N] [--seats N] [--alpha X] [--lambda X] [--explore X] [--start FILE]" );
+    eprintln!( "             [--validate N] [--seed N] [--threads N] [--out FILE]" );
+    std::process::exit( 1 );
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        games : 2000,
+        seats : 2,
+        alpha : 0.01,
+        lambda : 0.7,
+        explore : 0.1,
+        start : LearnedEval::default(),
+        validate : 20,
+        seed : 1,
+        threads : std::thread::available_parallelism().map( |n| n.get() ).unwrap_or( 1 ),
+        out : String::from( MODEL_FILE ),
+    };
+
+    let mut args = std::env::args().skip( 1 );
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else( || usage() );
+        match arg.as_str() {
+            "--games" => opts.games = value().parse().unwrap_or_else( |_| usage() ),
+            "--seats" => opts.seats = value().parse().unwrap_or_else( |_| usage() ),
+            "--alpha" => opts.alpha = value().parse().unwrap_or_else( |_| usage() ),
+            "--lambda" => opts.lambda = value().parse().unwrap_or_else( |_| usage() ),
+            "--explore" => opts.explore = value().parse().unwrap_or_else( |_| usage() ),
+            "--start" => {
+                let path = value();
+                opts.start = LearnedEval::load( &path ).unwrap_or_else( |err| {
+                    eprintln!( "{}", err );
+                    std::process::exit( 1 );
+                });
+            }
+            "--validate" => opts.validate = value().parse().unwrap_or_else( |_| usage() ),
+            "--seed" => opts.seed = value().parse().unwrap_or_else( |_| usage() ),
+            "--threads" => opts.threads = value().parse().unwrap_or_else( |_| usage() ),
+            "--out" => opts.out = value(),
+            _ => usage(),
+        }
+    }
+
+    if !(2..=4).contains( &opts.seats ) || opts.threads == 0 || !(0.0..=1.0).contains( &opts.lambda ) || !(0.0..=1.0).contains( &opts.explore ) {
+        usage();
+    }
+    opts
+}
+
+// Every position of one game, from the deal to the end
+fn self_play( opts : &Options, model : &LearnedEval, seats : u8, map_seed : u64 ) -> Vec<GameSnapshot> {
+    let start = generate_map( seats, &mut StdRng::seed_from_u64( map_seed ) );
+    let mut rng = StdRng::seed_from_u64( splitmix64( map_seed ) );
+
+    let mut pos = Position::new( start, 3, seats );
+    pos.advance();
+    let mut positions = vec![ pos.snapshot ];
+    while !pos.is_game_over() {
+        let mv = if rng.gen_bool( opts.explore ) {
+            biased_random_move( &pos.snapshot, pos.to_move, pos.seat_count(), model, &mut rng )
+        } else {
+            greedy_move( &pos.snapshot, pos.to_move, pos.seat_count(), model, &mut rng )
+        };
+        let Some(mv) = mv else { break };
+        pos = pos.play( mv );
+        positions.push( pos.snapshot );
+    }
+    positions
+}
+
+// Two player games of the model against the hand tuned evaluator on each map from
+// both seats, both played by the one-ply AI. Returns the model's share of the points.
+fn play_match( opts : &Options, model : LearnedEval, first_map : u64, maps : u64 ) -> f64 {
+    let games = (maps * 2) as usize;
+    let next_game = AtomicUsize::new( 0 );
+    let points = Mutex::new( 0.0 );
+    let learned = Entrant {
+        model : Some( model ),
+        ..Entrant::from( AIPolicy { difficulty : Difficulty::Medium, personality : Personality::Learned, ..Default::default() } )
+    };
+    let hand_tuned = Entrant::from( AIPolicy { difficulty : Difficulty::Medium, ..Default::default() } );
+
+    std::thread::scope( |scope| {
+        for _ in 0..opts.threads.min( games ) {
+            scope.spawn( || loop {
+                let game = next_game.fetch_add( 1, Ordering::Relaxed );
+                if game >= games {
+                    break;
+                }
+                let map_seed = first_map + (game / 2) as u64;
+                let model_seat = game % 2;
+                let start = generate_map( 0b11, &mut StdRng::seed_from_u64( map_seed ) );
+
+                let mut entrants = [None; 4];
+                entrants[model_seat] = Some( learned );
+                entrants[1 - model_seat] = Some( hand_tuned );
+
+                let mut rng = StdRng::seed_from_u64( splitmix64( map_seed ^ ((model_seat as u64 + 1) << 40) ) );
+                let record = play_game( start, entrants, SearchLimits::default(), &mut rng );
+
+                let (mine, theirs) = (record.scores[model_seat], record.scores[1 - model_seat]);
+                let result = if mine > theirs { 1.0 } else if mine == theirs { 0.5 } else { 0.0 };
+                *points.lock().unwrap() += result;
+            });
+        }
+    });
+
+    points.into_inner().unwrap() / games as f64
+}
+
+fn main() {
+    let opts = parse_args();
+    let seats = (1u8 << opts.seats) - 1;
+    let mut model = opts.start;
+
+    eprintln!( "training on {} games of {} players", opts.games, opts.seats );
+
+    let mut played = 0;
+    while played < opts.games {
+        let batch = (opts.threads as u64).min( opts.games - played );
+        let current = model;
+        let games : Vec<Vec<GameSnapshot>> = std::thread::scope( |scope| {
+            let handles : Vec<_> = (0..batch).map( |i| {
+                let map_seed = opts.seed + played + i;
+                let (opts, current) = (&opts, &current);
+                scope.spawn( move || self_play( opts, current, seats, map_seed ) )
+            }).collect();
+            handles.into_iter().map( |handle| handle.join().unwrap() ).collect()
+        });
+
+        for positions in &games {
+            model.td_update( positions, seats, opts.alpha, opts.lambda );
+        }
+
+        let before = played;
+        played += batch;
+        if played / 100 != before / 100 {
+            eprintln!( "{:>6} {:?}", played, model.weights );
+        }
+    }
+
+    let score = play_match( &opts, model, opts.seed + opts.games, opts.validate );
+    println!( "{}", model.to_text() );
+    println!( "scored {:.1}% against the hand tuned evaluator over {} games", score * 100.0, opts.validate * 2 );
+
+    match model.save( &opts.out ) {
+        Ok(()) => println!( "wrote {}", opts.out ),
+        Err(err) => eprintln!( "couldn't write {}", err ),
+    }
+}
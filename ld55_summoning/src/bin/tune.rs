@@ -0,0 +1,215 @@
+//! Self-play tuner for the evaluation weights, using SPSA.
+//!
+//! Every iteration nudges all the weights at once in a random direction, plays the
+//! nudged-up set against the nudged-down set on a few maps (both ways round), and
+//! moves the weights towards whichever side did better. At the end the result is
+//! played against the baseline and written out as a weights file, which the game
+//! and the tournament runner can load.
+//!
+//!     tune [options]
+//!
+//!   --iterations N   SPSA steps (default 200)
+//!   --maps N         maps per step, each played from both seats (default 2)
+//!   --policy P       the AI that plays the games (default medium, the fastest)
+//!   --depth N        search depth limit when the policy searches
+//!   --time-ms N      thinking time per move when the policy searches (default 50)
+//!   --rate X         step size (default 2.0)
+//!   --start FILE     weights to start from instead of the defaults
+//!   --baseline FILE  weights to check the result against instead of the defaults
+//!   --validate N     maps for the final check against the baseline (default 20)
+//!   --seed N         first map seed (default 1)
+//!   --threads N      games to run at once (default: one per core)
+//!   --out FILE       where to write the tuned weights (default ai_weights.txt, which the game picks up)
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use web_time::Duration;
+
+use ld55_summoning::ai::AIPolicy;
+use ld55_summoning::arena::{play_game, Entrant};
+use ld55_summoning::gamestate::{generate_map, splitmix64, EvalWeights, WEIGHTS_FILE};
+use ld55_summoning::search::SearchLimits;
+
+struct Options {
+    iterations : u32,
+    maps : u64,
+    policy : AIPolicy,
+    limits : SearchLimits,
+    rate : f64,
+    start : EvalWeights,
+    baseline : EvalWeights,
+    validate : u64,
+    seed : u64,
+    threads : usize,
+    out : String,
+}
+
+fn usage() -> ! {
+    eprintln!( "usage: tune [--iterations N] [--maps N] [--policy P] [--depth N] [--time-ms N] [--rate X]" );
+    eprintln!( "            [--start FILE] [--baseline FILE] [--validate N] [--seed N] [--threads N] [--out FILE]" );
+    std::process::exit( 1 );
+}
+
+fn load_or_exit( path : &str ) -> EvalWeights {
+    EvalWeights::load( path ).unwrap_or_else( |err| {
+        eprintln!( "{}", err );
+        std::process::exit( 1 );
+    })
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        iterations : 200,
+        maps : 2,
+        policy : AIPolicy::default(),
+        limits : SearchLimits::time( Duration::from_millis( 50 ) ),
+        rate : 2.0,
+        start : EvalWeights::default(),
+        baseline : EvalWeights::default(),
+        validate : 20,
+        seed : 1,
+        threads : std::thread::available_parallelism().map( |n| n.get() ).unwrap_or( 1 ),
+        out : String::from( WEIGHTS_FILE ),
+    };
+
+    let mut args = std::env::args().skip( 1 );
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else( || usage() );
+        match arg.as_str() {
+            "--iterations" => opts.iterations = value().parse().unwrap_or_else( |_| usage() ),
+            "--maps" => opts.maps = value().parse().unwrap_or_else( |_| usage() ),
+            "--policy" => opts.policy = AIPolicy::parse( &value() ).unwrap_or_else( || usage() ),
+            "--depth" => {
+                opts.limits.max_depth = value().parse().unwrap_or_else( |_| usage() );
+                opts.limits.time_budget = None;
+            }
+            "--time-ms" => opts.limits.time_budget = Some( Duration::from_millis( value().parse().unwrap_or_else( |_| usage() ) ) ),
+            "--rate" => opts.rate = value().parse().unwrap_or_else( |_| usage() ),
+            "--start" => opts.start = load_or_exit( &value() ),
+            "--baseline" => opts.baseline = load_or_exit( &value() ),
+            "--validate" => opts.validate = value().parse().unwrap_or_else( |_| usage() ),
+            "--seed" => opts.seed = value().parse().unwrap_or_else( |_| usage() ),
+            "--threads" => opts.threads = value().parse().unwrap_or_else( |_| usage() ),
+            "--out" => opts.out = value(),
+            _ => usage(),
+        }
+    }
+
+    if opts.maps == 0 || opts.threads == 0 {
+        usage();
+    }
+    opts
+}
+
+// The tuner works in units of a tenth of each default weight, so one step means
+// about the same amount for every weight
+fn scales() -> [f64; 6] {
+    EvalWeights::default().values().map( |v| v as f64 / 10.0 )
+}
+
+fn to_units( weights : &EvalWeights ) -> [f64; 6] {
+    let scales = scales();
+    let mut units = [0.0; 6];
+    for ((u, v), s) in units.iter_mut().zip( weights.values() ).zip( scales ) {
+        *u = v as f64 / s;
+    }
+    units
+}
+
+fn from_units( units : &[f64; 6] ) -> EvalWeights {
+    let scales = scales();
+    let mut values = [0; 6];
+    for (((v, u), s), (low, high)) in values.iter_mut().zip( units ).zip( scales ).zip( EvalWeights::LIMITS ) {
+        // Kept in range so the weights file loads again
+        *v = ((u * s).round() as i32).clamp( low, high );
+    }
+    EvalWeights::from_values( values )
+}
+
+// Play `a` against `b` on each map from both seats. Returns a's share of the points,
+// 1.0 for a win, 0.5 for a draw.
+fn play_match( opts : &Options, a : EvalWeights, b : EvalWeights, first_map : u64, maps : u64 ) -> f64 {
+    let games = (maps * 2) as usize;
+    let next_game = AtomicUsize::new( 0 );
+    let points = Mutex::new( 0.0 );
+
+    std::thread::scope( |scope| {
+        for _ in 0..opts.threads.min( games ) {
+            scope.spawn( || loop {
+                let game = next_game.fetch_add( 1, Ordering::Relaxed );
+                if game >= games {
+                    break;
+                }
+                let map_seed = first_map + (game / 2) as u64;
+                let a_seat = game % 2;
+                let start = generate_map( 0b11, &mut StdRng::seed_from_u64( map_seed ) );
+
+                let mut entrants = [None; 4];
+                entrants[a_seat] = Some( Entrant { weights : a, ..opts.policy.into() } );
+                entrants[1 - a_seat] = Some( Entrant { weights : b, ..opts.policy.into() } );
+
+                let mut rng = StdRng::seed_from_u64( splitmix64( map_seed ^ ((a_seat as u64 + 1) << 40) ) );
+                let record = play_game( start, entrants, opts.limits, &mut rng );
+
+                let (mine, theirs) = (record.scores[a_seat], record.scores[1 - a_seat]);
+                let result = if mine > theirs { 1.0 } else if mine == theirs { 0.5 } else { 0.0 };
+                *points.lock().unwrap() += result;
+            });
+        }
+    });
+
+    points.into_inner().unwrap() / games as f64
+}
+
+fn main() {
+    let opts = parse_args();
+    let mut rng = StdRng::seed_from_u64( splitmix64( opts.seed ) );
+    let mut theta = to_units( &opts.start );
+
+    eprintln!( "tuning for {} over {} iterations", opts.policy.label(), opts.iterations );
+
+    // The usual SPSA gain schedules
+    let big_a = opts.iterations as f64 / 10.0;
+    let c = 1.0;
+    let mut next_map = opts.seed;
+    for k in 0..opts.iterations {
+        let a_k = opts.rate / (k as f64 + 1.0 + big_a).powf( 0.602 );
+        let c_k = c / (k as f64 + 1.0).powf( 0.101 );
+
+        let delta : [f64; 6] = std::array::from_fn( |_| if rng.gen() { 1.0 } else { -1.0 } );
+        let mut plus = theta;
+        let mut minus = theta;
+        for i in 0..6 {
+            plus[i] += c_k * delta[i];
+            minus[i] -= c_k * delta[i];
+        }
+
+        // How much better the plus side did, from -1 to 1
+        let diff = 2.0 * play_match( &opts, from_units( &plus ), from_units( &minus ), next_map, opts.maps ) - 1.0;
+        next_map += opts.maps;
+
+        for (t, d) in theta.iter_mut().zip( delta ) {
+            *t += a_k * diff / (2.0 * c_k * d);
+        }
+        // Keep theta inside the limits from_units applies, or it could drift off where nothing changes
+        for (i, (t, s)) in theta.iter_mut().zip( scales() ).enumerate() {
+            let max = if i == 3 || i == 4 { 100.0 / s } else { f64::MAX };
+            *t = t.clamp( 0.0, max );
+        }
+
+        eprintln!( "{:>4} {:+.2}  {:?}", k + 1, diff, from_units( &theta ).values() );
+    }
+
+    let tuned = from_units( &theta );
+    let score = play_match( &opts, tuned, opts.baseline, next_map, opts.validate );
+    println!( "{}", tuned.to_text() );
+    println!( "scored {:.1}% against the baseline over {} games", score * 100.0, opts.validate * 2 );
+
+    match tuned.save( &opts.out ) {
+        Ok(()) => println!( "wrote {}", opts.out ),
+        Err(err) => eprintln!( "couldn't write {}", err ),
+    }
+}
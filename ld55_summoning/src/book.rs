@@ -0,0 +1,315 @@
+// Opening book.
+//
+// Every game is on a different random map, so positions are looked up by a
+// normalized key rather than the board itself. Early on the players are far
+// apart and what matters is the shape of the mover's own stacks: how much power
+// each has and how far it can split in each direction. The key is built from just
+// that, with ray lengths capped, and with the directions turned and flipped to
+// whichever of the 12 hex symmetries sorts first, so mirror image starts share
+// their book moves. Stacks are looked up by their place in the sorted list, and
+// moves by direction after the same turn/flip.
+//
+// Book files have one move per line: the key in hex, the move as
+// "<stack>.<direction>.<amount>", then how many games it was played in and the
+// points it got there (2 for a win, 1 for sharing first place). # starts a comment.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::gamestate::{move_dir, splitmix64, MapDirection, MapSpaceContents, Position, SplitMove, INVALID};
+
+// Rays longer than this all look the same to the book
+const RAY_CAP : u8 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BookMove {
+    pub stack : u8,   // index into the normalized stack order
+    pub dir : u8,     // normalized direction
+    pub amount : u8,
+    pub games : u32,
+    pub points : u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OpeningBook {
+    entries : HashMap<u64, Vec<BookMove>>,
+}
+
+// Power and capped ray lengths of one stack
+type Shape = (u8, [u8; 6]);
+
+// The mover's stacks in normalized order, and the symmetry that got them there
+struct Normalized {
+    key : u64,
+    stacks : Vec<i32>,
+    symmetry : usize,
+}
+
+// MapDirection::iterator goes round the hex, so turning is adding to the index
+// and flipping is negating it. Symmetries 0-5 are turns, 6-11 turns of the flip.
+fn apply_symmetry( symmetry : usize, dir : usize ) -> usize {
+    let turn = symmetry % 6;
+    if symmetry < 6 { (dir + turn) % 6 } else { (6 - dir + turn) % 6 }
+}
+
+fn ray_lengths( pos : &Position, ndx : i32 ) -> [u8; 6] {
+    let mut rays = [0; 6];
+    for (ray, dir) in rays.iter_mut().zip( MapDirection::iterator() ) {
+        let mut curr = move_dir( ndx, dir );
+        while *ray < RAY_CAP && curr != INVALID as i32 {
+            let space = &pos.snapshot.map.spaces[curr as usize];
+            if space.contents != MapSpaceContents::Playable || space.power != 0 {
+                break;
+            }
+            *ray += 1;
+            curr = move_dir( curr, dir );
+        }
+    }
+    rays
+}
+
+fn normalize( pos : &Position ) -> Normalized {
+    let player = (pos.to_move + 1) as u8;
+    let stacks : Vec<(i32, u8, [u8; 6])> = pos.snapshot.map.spaces.iter()
+        .filter( |space| space.player == player && space.power > 1 )
+        .map( |space| (space.ndx, space.power, ray_lengths( pos, space.ndx )) )
+        .collect();
+
+    let mut best : Option<(Vec<Shape>, Vec<i32>, usize)> = None;
+    for symmetry in 0..12 {
+        let mut shapes : Vec<(u8, [u8; 6], i32)> = stacks.iter().map( |(ndx, power, rays)| {
+            let mut turned = [0; 6];
+            for (dir, ray) in rays.iter().enumerate() {
+                turned[apply_symmetry( symmetry, dir )] = *ray;
+            }
+            (*power, turned, *ndx)
+        }).collect();
+        shapes.sort();
+        let shape_list : Vec<Shape> = shapes.iter().map( |(power, rays, _)| (*power, *rays) ).collect();
+        if best.as_ref().is_none_or( |(best_list, _, _)| shape_list < *best_list ) {
+            best = Some( (shape_list, shapes.iter().map( |s| s.2 ).collect(), symmetry) );
+        }
+    }
+
+    let (shape_list, stacks, symmetry) = best.unwrap();
+    let mut key = splitmix64( pos.seat_count() as u64 );
+    for (power, rays) in shape_list {
+        let packed = rays.iter().fold( power as u64, |acc, ray| (acc << 8) | *ray as u64 );
+        key = splitmix64( key ^ packed );
+    }
+    Normalized { key, stacks, symmetry }
+}
+
+impl OpeningBook {
+    pub fn len( &self ) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn key( pos : &Position ) -> u64 {
+        normalize( pos ).key
+    }
+
+    // Count a game where `mv` was played from `pos` and got `points` for the mover
+    pub fn record( &mut self, pos : &Position, mv : SplitMove, points : u32 ) {
+        let norm = normalize( pos );
+        let Some(stack) = norm.stacks.iter().position( |ndx| *ndx == mv.from ) else { return };
+        let Some(dir) = MapDirection::iterator().position( |dir| pos.snapshot.map.search_dir( mv.from, dir ) == mv.to ) else { return };
+        let dir = apply_symmetry( norm.symmetry, dir ) as u8;
+
+        let moves = self.entries.entry( norm.key ).or_default();
+        match moves.iter_mut().find( |m| m.stack as usize == stack && m.dir == dir && m.amount == mv.amount ) {
+            Some(book_move) => {
+                book_move.games += 1;
+                book_move.points += points;
+            }
+            None => moves.push( BookMove { stack : stack as u8, dir, amount : mv.amount, games : 1, points } ),
+        }
+    }
+
+    // Book moves for the position, turned back into real moves
+    pub fn moves( &self, pos : &Position ) -> Vec<(SplitMove, BookMove)> {
+        let norm = normalize( pos );
+        let Some(moves) = self.entries.get( &norm.key ) else { return Vec::new() };
+        moves.iter().filter_map( |book_move| {
+            let from = *norm.stacks.get( book_move.stack as usize )?;
+            let (_, dir) = MapDirection::iterator().enumerate().find( |(dir, _)| apply_symmetry( norm.symmetry, *dir ) == book_move.dir as usize )?;
+            let to = pos.snapshot.map.search_dir( from, dir );
+            let power = pos.snapshot.map.spaces[from as usize].power;
+            if to == from || to == INVALID as i32 || book_move.amount >= power {
+                return None;
+            }
+            Some( (SplitMove { from, to, amount : book_move.amount }, *book_move) )
+        }).collect()
+    }
+
+    // A book move picked at random, weighted by the points it scored
+    pub fn lookup( &self, pos : &Position, rng : &mut impl Rng ) -> Option<SplitMove> {
+        let moves = self.moves( pos );
+        let total : u32 = moves.iter().map( |(_, book_move)| book_move.points ).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = rng.gen_range( 0..total );
+        for (mv, book_move) in moves {
+            if pick < book_move.points {
+                return Some( mv );
+            }
+            pick -= book_move.points;
+        }
+        None
+    }
+
+    // Drop moves that haven't been played enough to say anything
+    pub fn prune( &mut self, min_games : u32 ) {
+        for moves in self.entries.values_mut() {
+            moves.retain( |m| m.games >= min_games );
+        }
+        self.entries.retain( |_, moves| !moves.is_empty() );
+    }
+
+    pub fn merge( &mut self, other : &OpeningBook ) {
+        for (key, other_moves) in &other.entries {
+            let moves = self.entries.entry( *key ).or_default();
+            for other_move in other_moves {
+                match moves.iter_mut().find( |m| (m.stack, m.dir, m.amount) == (other_move.stack, other_move.dir, other_move.amount) ) {
+                    Some(book_move) => {
+                        book_move.games += other_move.games;
+                        book_move.points += other_move.points;
+                    }
+                    None => moves.push( *other_move ),
+                }
+            }
+        }
+    }
+
+    pub fn parse( text : &str ) -> Result<OpeningBook, String> {
+        let mut book = OpeningBook::default();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.split( '#' ).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad_line = || format!( "line {}: expected <key> <stack>.<dir>.<amount> <games> <points>", line_num + 1 );
+            let fields : Vec<&str> = line.split_whitespace().collect();
+            let [key, mv, games, points] = fields[..] else { return Err( bad_line() ) };
+            let mv : Vec<u8> = mv.split( '.' ).map( |n| n.parse() ).collect::<Result<_, _>>().map_err( |_| bad_line() )?;
+            let [stack, dir, amount] = mv[..] else { return Err( bad_line() ) };
+            let book_move = BookMove {
+                stack,
+                dir,
+                amount,
+                games : games.parse().map_err( |_| bad_line() )?,
+                points : points.parse().map_err( |_| bad_line() )?,
+            };
+            let key = u64::from_str_radix( key, 16 ).map_err( |_| bad_line() )?;
+            book.entries.entry( key ).or_default().push( book_move );
+        }
+        Ok( book )
+    }
+
+    // Sorted so rebuilt books diff nicely
+    pub fn to_text( &self ) -> String {
+        let mut keys : Vec<&u64> = self.entries.keys().collect();
+        keys.sort();
+        let mut text = String::new();
+        for key in keys {
+            let mut moves = self.entries[key].clone();
+            moves.sort_by_key( |m| (std::cmp::Reverse( m.games ), m.stack, m.dir, m.amount) );
+            for m in moves {
+                text += &format!( "{:016x} {}.{}.{} {} {}\n", key, m.stack, m.dir, m.amount, m.games, m.points );
+            }
+        }
+        text
+    }
+
+    pub fn load( path : &str ) -> Result<OpeningBook, String> {
+        let text = std::fs::read_to_string( path ).map_err( |err| format!( "{}: {}", path, err ) )?;
+        OpeningBook::parse( &text ).map_err( |err| format!( "{}: {}", path, err ) )
+    }
+
+    pub fn save( &self, path : &str ) -> Result<(), String> {
+        std::fs::write( path, self.to_text() ).map_err( |err| format!( "{}: {}", path, err ) )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamestate::GameSnapshot;
+
+    const CENTER : i32 = 54;
+
+    // A lone stack for seat 0 in the middle of an empty board, with the given
+    // number of open hexes in each direction
+    fn lone_stack( power : u8, rays : [u8; 6] ) -> Position {
+        let mut snapshot = GameSnapshot::default();
+        for (ndx, space) in snapshot.map.spaces.iter_mut().enumerate() {
+            space.ndx = ndx as i32;
+        }
+        let center = &mut snapshot.map.spaces[CENTER as usize];
+        center.contents = MapSpaceContents::Playable;
+        center.player = 1;
+        center.power = power;
+        for (dir, length) in MapDirection::iterator().zip( rays ) {
+            let mut curr = CENTER;
+            for _ in 0..length {
+                curr = move_dir( curr, dir );
+                snapshot.map.spaces[curr as usize].contents = MapSpaceContents::Playable;
+            }
+        }
+        Position::new( snapshot, 0, 0b0011 )
+    }
+
+    #[test]
+    fn every_symmetry_has_the_same_key() {
+        let rays = [ 0, 1, 3, 4, 2, 1 ];
+        let base = lone_stack( 12, rays );
+        assert_eq!( ray_lengths( &base, CENTER ), rays );
+
+        for symmetry in 0..12 {
+            let mut turned = [0; 6];
+            for (dir, length) in rays.iter().enumerate() {
+                turned[apply_symmetry( symmetry, dir )] = *length;
+            }
+            let pos = lone_stack( 12, turned );
+            assert_eq!( ray_lengths( &pos, CENTER ), turned );
+            assert_eq!( OpeningBook::key( &pos ), OpeningBook::key( &base ), "symmetry {}", symmetry );
+        }
+
+        // Whereas a different shape doesn't
+        assert_ne!( OpeningBook::key( &lone_stack( 11, rays ) ), OpeningBook::key( &base ) );
+        assert_ne!( OpeningBook::key( &lone_stack( 12, [ 0, 1, 3, 4, 1, 2 ] ) ), OpeningBook::key( &base ) );
+    }
+
+    #[test]
+    fn book_moves_turn_with_the_board() {
+        let rays = [ 0, 1, 3, 4, 2, 1 ];
+        let base = lone_stack( 12, rays );
+        let dir = MapDirection::iterator().nth( 3 ).unwrap();
+        let mut book = OpeningBook::default();
+        book.record( &base, SplitMove { from : CENTER, to : base.snapshot.map.search_dir( CENTER, dir ), amount : 5 }, 2 );
+
+        for symmetry in 0..12 {
+            let mut turned = [0; 6];
+            for (dir, length) in rays.iter().enumerate() {
+                turned[apply_symmetry( symmetry, dir )] = *length;
+            }
+            let pos = lone_stack( 12, turned );
+            let moves = book.moves( &pos );
+            assert_eq!( moves.len(), 1 );
+
+            // The four hex ray is the one the move went down, wherever it points now
+            let (mv, _) = moves[0];
+            assert_eq!( mv.amount, 5 );
+            assert_eq!( mv.from, CENTER );
+            let length = MapDirection::iterator().map( |dir| pos.snapshot.map.search_dir( CENTER, dir ) )
+                .position( |to| to == mv.to ).map( |d| turned[d] );
+            assert_eq!( length, Some( 4 ) );
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Time controls.
+//
+// Either a chess clock, with a stretch of time for the whole game and a little
+// added back after every move, or a fixed allowance for each turn. Running out
+// doesn't lose the game, the player just gets a move made for them.
+
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimeControl {
+    #[default]
+    Untimed,
+    Total { base : Duration, increment : Duration },
+    PerTurn( Duration ),
+}
+
+// What the setup screen cycles through
+const PRESETS : [TimeControl; 5] = [
+    TimeControl::Untimed,
+    TimeControl::Total { base : Duration::from_secs( 300 ), increment : Duration::from_secs( 5 ) },
+    TimeControl::Total { base : Duration::from_secs( 120 ), increment : Duration::from_secs( 2 ) },
+    TimeControl::PerTurn( Duration::from_secs( 30 ) ),
+    TimeControl::PerTurn( Duration::from_secs( 10 ) ),
+];
+
+impl TimeControl {
+    pub fn name( &self ) -> String {
+        match self {
+            TimeControl::Untimed => "Untimed".to_string(),
+            TimeControl::Total { base, increment } => format!( "{} min + {} s", base.as_secs() / 60, increment.as_secs() ),
+            TimeControl::PerTurn( limit ) => format!( "{} s per turn", limit.as_secs() ),
+        }
+    }
+
+    pub fn next( &self ) -> TimeControl {
+        let current = PRESETS.iter().position( |preset| preset == self ).unwrap_or( 0 );
+        PRESETS[(current + 1) % PRESETS.len()]
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Clocks {
+    pub control : TimeControl,
+    pub remaining : [Duration; 4],  // the rest of the game, or of this turn for a per turn limit
+}
+
+impl Clocks {
+    pub fn new( control : TimeControl ) -> Clocks {
+        let start = match control {
+            TimeControl::Untimed => Duration::ZERO,
+            TimeControl::Total { base, .. } => base,
+            TimeControl::PerTurn( limit ) => limit,
+        };
+        Clocks { control, remaining : [start; 4] }
+    }
+
+    pub fn is_timed( &self ) -> bool {
+        self.control != TimeControl::Untimed
+    }
+
+    // Runs the clock of the seat to move, true once it's out of time
+    pub fn tick( &mut self, seat : usize, elapsed : Duration ) -> bool {
+        if !self.is_timed() {
+            return false;
+        }
+        self.remaining[seat] = self.remaining[seat].saturating_sub( elapsed );
+        self.remaining[seat].is_zero()
+    }
+
+    // The seat's turn is over, time out or not
+    pub fn turn_done( &mut self, seat : usize ) {
+        match self.control {
+            TimeControl::Untimed => {}
+            TimeControl::Total { increment, .. } => self.remaining[seat] += increment,
+            TimeControl::PerTurn( limit ) => self.remaining[seat] = limit,
+        }
+    }
+
+    // How long a computer player can spend on this move without running short
+    // later. About a twentieth of what's left on a chess clock, plus most of the
+    // increment, or most of a per turn allowance.
+    pub fn move_budget( &self, seat : usize ) -> Option<Duration> {
+        let remaining = self.remaining[seat];
+        match self.control {
+            TimeControl::Untimed => None,
+            TimeControl::Total { increment, .. } => Some( (remaining / 20 + increment * 3 / 4).min( remaining / 2 ) ),
+            TimeControl::PerTurn( _ ) => Some( remaining * 3 / 4 ),
+        }
+    }
+}
@@ -0,0 +1,285 @@
+// Exact endgame solver.
+//
+// Once only a few moves are left on the board the game can be searched right to
+// the end, scoring finished games with calc_simple_score, so the answer is a
+// proven result rather than a guess from evaluate_position.
+//
+// The score is the final margin in stacks for the seat to move: its own stack
+// count minus the best of the other seats. Two player games use alpha-beta on
+// that margin. With more seats every seat maximizes its own margin (max-n), so
+// the result is only proven under that assumption about how the others play.
+//
+// The reduced move generator is always used, it gives exactly the same results
+// with fewer moves to try (see movegen.rs).
+
+use std::collections::HashMap;
+
+use web_time::{Duration, Instant};
+
+use crate::gamestate::{Position, SplitMove};
+use crate::movegen::MoveGen;
+use crate::search::StopFlag;
+
+const INF : i32 = 1_000_000;
+
+// How often (in nodes) to look at the clock, must be a power of two
+const TIME_CHECK_NODES : u64 = 1024;
+
+#[derive(Copy, Clone, Debug)]
+pub struct EndgameResult {
+    pub best : SplitMove,
+    pub margin : i32,  // final stacks ahead of the best other seat, for the seat to move
+    pub nodes : u64,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+pub struct EndgameSolver {
+    pub max_moves : usize,  // only solve with this many moves or fewer left for all seats together
+    pub node_limit : u64,   // give up past this, so a bad guess can't hang the game
+    pub stop : StopFlag,
+    deadline : Option<Instant>,
+    table : HashMap<u64, (i32, Bound)>,
+    multi_table : HashMap<u64, [i32; 4]>,
+    nodes : u64,
+    aborted : bool,
+}
+
+impl Default for EndgameSolver {
+    fn default() -> Self {
+        EndgameSolver {
+            max_moves : 20,
+            node_limit : 2_000_000,
+            stop : StopFlag::default(),
+            deadline : None,
+            table : HashMap::new(),
+            multi_table : HashMap::new(),
+            nodes : 0,
+            aborted : false,
+        }
+    }
+}
+
+// Moves left for every seat, a rough measure of how big the rest of the game is
+pub fn moves_left( pos : &Position ) -> usize {
+    (0..4).filter( |seat| pos.is_seated( *seat ) ).map( |seat| MoveGen::Reduced.moves( &pos.snapshot, seat ).len() ).sum()
+}
+
+// Final stacks ahead of the best other seat, for every seat
+pub fn final_margins( pos : &Position ) -> [i32; 4] {
+    let scores : [i32; 4] = std::array::from_fn( |seat| pos.snapshot.calc_simple_score( seat as i32 ) );
+    let mut margins = [0; 4];
+    for seat in (0..4).filter( |seat| pos.is_seated( *seat ) ) {
+        let best_other = (0..4).filter( |other| *other != seat && pos.is_seated( *other ) ).map( |other| scores[other] ).max().unwrap_or( 0 );
+        margins[seat] = scores[seat] - best_other;
+    }
+    margins
+}
+
+impl EndgameSolver {
+    pub fn is_active( &self, pos : &Position ) -> bool {
+        !pos.is_game_over() && moves_left( pos ) <= self.max_moves
+    }
+
+    // The tables only hold proven values so they could be kept, but they'd grow forever
+    pub fn clear( &mut self ) {
+        self.table.clear();
+        self.multi_table.clear();
+    }
+
+    // None if the position is too big to solve, or the search ran out of nodes or time
+    // or was stopped. Anything solved before that stays in the tables for next time.
+    pub fn solve( &mut self, pos : &Position, time_budget : Option<Duration> ) -> Option<EndgameResult> {
+        if !self.is_active( pos ) {
+            return None;
+        }
+        let moves = MoveGen::Reduced.moves( &pos.snapshot, pos.to_move );
+        self.solve_moves( pos, &moves, time_budget )
+    }
+
+    // Best of the given moves and its proven margin. Moves that aren't in the reduced
+    // list are fine too, they just get searched as they are.
+    pub fn solve_moves( &mut self, pos : &Position, moves : &[SplitMove], time_budget : Option<Duration> ) -> Option<EndgameResult> {
+        self.nodes = 0;
+        self.aborted = false;
+        self.deadline = time_budget.map( |budget| Instant::now() + budget );
+        if self.table.len() + self.multi_table.len() > self.node_limit as usize {
+            self.clear();
+        }
+
+        let me = pos.to_move;
+        let two_player = pos.seat_count() == 2;
+        let mut best : Option<(SplitMove, i32)> = None;
+        for mv in moves {
+            let child = pos.play( *mv );
+            let margin = if two_player {
+                let alpha = best.map_or( -INF, |(_, margin)| margin );
+                self.child_value( pos, &child, alpha, INF )
+            } else {
+                self.maxn( &child )[me]
+            };
+            if self.aborted {
+                return None;
+            }
+            if best.is_none_or( |(_, best_margin)| margin > best_margin ) {
+                best = Some( (*mv, margin) );
+            }
+        }
+
+        best.map( |(best, margin)| EndgameResult { best, margin, nodes : self.nodes } )
+    }
+
+    fn tick( &mut self ) -> bool {
+        self.nodes += 1;
+        if self.nodes & (TIME_CHECK_NODES - 1) == 0 {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    self.aborted = true;
+                }
+            }
+            if self.stop.is_stopped() {
+                self.aborted = true;
+            }
+        }
+        if self.nodes > self.node_limit {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    // Same sign handling as AlphaBeta, a seat can move twice in a row when the other passes
+    fn child_value( &mut self, parent : &Position, child : &Position, alpha : i32, beta : i32 ) -> i32 {
+        if child.to_move == parent.to_move {
+            self.negamax( child, alpha, beta )
+        } else {
+            -self.negamax( child, -beta, -alpha )
+        }
+    }
+
+    fn negamax( &mut self, pos : &Position, mut alpha : i32, mut beta : i32 ) -> i32 {
+        if self.tick() {
+            return 0;
+        }
+        if pos.is_game_over() {
+            return final_margins( pos )[pos.to_move];
+        }
+
+        let key = pos.hash();
+        if let Some((value, bound)) = self.table.get( &key ) {
+            match bound {
+                Bound::Exact => return *value,
+                Bound::Lower => alpha = alpha.max( *value ),
+                Bound::Upper => beta = beta.min( *value ),
+            }
+            if alpha >= beta {
+                return *value;
+            }
+        }
+
+        let alpha_orig = alpha;
+        let mut best_value = -INF;
+        for mv in MoveGen::Reduced.moves( &pos.snapshot, pos.to_move ) {
+            let child = pos.play( mv );
+            let value = self.child_value( pos, &child, alpha, beta );
+            if self.aborted {
+                return 0;
+            }
+            best_value = best_value.max( value );
+            alpha = alpha.max( value );
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best_value <= alpha_orig {
+            Bound::Upper
+        } else if best_value >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.insert( key, (best_value, bound) );
+        best_value
+    }
+
+    fn maxn( &mut self, pos : &Position ) -> [i32; 4] {
+        if self.tick() {
+            return [0; 4];
+        }
+        if pos.is_game_over() {
+            return final_margins( pos );
+        }
+
+        let key = pos.hash();
+        if let Some(values) = self.multi_table.get( &key ) {
+            return *values;
+        }
+
+        let mover = pos.to_move;
+        let mut best = [-INF; 4];
+        for mv in MoveGen::Reduced.moves( &pos.snapshot, mover ) {
+            let values = self.maxn( &pos.play( mv ) );
+            if self.aborted {
+                return [0; 4];
+            }
+            if values[mover] > best[mover] {
+                best = values;
+            }
+        }
+        self.multi_table.insert( key, best );
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::gamestate::{gen_split_moves, generate_map};
+    use crate::search::{AlphaBeta, SearchLimits, WIN_SCORE};
+
+    // Random two player games, stopped once only a few moves are left
+    fn small_endgames( count : u64 ) -> Vec<Position> {
+        (0..count).filter_map( |seed| {
+            let mut rng = StdRng::seed_from_u64( seed );
+            let mut pos = Position::new( generate_map( 0b0011, &mut rng ), 3, 0b0011 );
+            pos.advance();
+            while !pos.is_game_over() && moves_left( &pos ) > 12 {
+                pos = pos.play( *gen_split_moves( &pos.snapshot, pos.to_move ).choose( &mut rng ).unwrap() );
+            }
+            (!pos.is_game_over()).then_some( pos )
+        }).collect()
+    }
+
+    #[test]
+    fn solver_agrees_with_a_full_depth_search() {
+        let endgames = small_endgames( 12 );
+        assert!( endgames.len() >= 8 );
+        for pos in endgames {
+            let mut solver = EndgameSolver::default();
+            let solved = solver.solve( &pos, None ).expect( "small enough to solve" );
+
+            // Deep enough that every line plays out to the end
+            let mut search = AlphaBeta::new( 16 );
+            let searched = search.search( &pos, SearchLimits::depth( 100 ) );
+            assert_eq!( searched.score, solved.margin * WIN_SCORE );
+
+            // And the move it picked really gets that margin
+            let after = pos.play( solved.best );
+            let margin = match solver.solve( &after, None ) {
+                Some(reply) if after.to_move == pos.to_move => reply.margin,
+                Some(reply) => -reply.margin,
+                None => final_margins( &after )[pos.to_move],
+            };
+            assert_eq!( margin, solved.margin );
+        }
+    }
+}
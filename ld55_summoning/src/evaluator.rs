@@ -0,0 +1,24 @@
+// Position evaluation as the engines see it. The hand tuned evaluate_position is one
+// evaluator, the model trained by self-play (see learned.rs) is another.
+
+use crate::gamestate::{evaluate_position_with, splitmix64, EvalWeights, GameSnapshot};
+
+pub trait Evaluator : Send + Sync {
+    // How good the position is for each seat, in about the same units as
+    // evaluate_position: one stack on the board is worth about 10000
+    fn evaluate( &self, snap : &GameSnapshot ) -> [i32; 4];
+
+    // The same for any two evaluators that always agree, so the search can tell
+    // when its stored scores are still good
+    fn fingerprint( &self ) -> u64;
+}
+
+impl Evaluator for EvalWeights {
+    fn evaluate( &self, snap : &GameSnapshot ) -> [i32; 4] {
+        evaluate_position_with( *snap, self )
+    }
+
+    fn fingerprint( &self ) -> u64 {
+        self.values().iter().fold( 0, |acc, v| splitmix64( acc ^ *v as u32 as u64 ) )
+    }
+}
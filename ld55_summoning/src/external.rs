@@ -0,0 +1,109 @@
+// Running an engine program that speaks the text protocol (see protocol.rs).
+//
+// The engine's output is read on a thread of its own and handed over through a
+// channel, so the game can check for a move every frame without blocking.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command as Process, Stdio};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Mutex;
+
+use crate::gamestate::{Position, SplitMove};
+use crate::protocol::{set_stacks, stacks_of, Command, Reply};
+use crate::search::SearchLimits;
+
+pub struct ExternalEngine {
+    pub name : String,
+    child : Child,
+    stdin : ChildStdin,
+    replies : Mutex<Receiver<Reply>>,  // only so the engine can live in a Bevy resource
+}
+
+impl ExternalEngine {
+    // The command line is split on whitespace, the first word is the program
+    pub fn launch( command_line : &str ) -> Result<ExternalEngine, String> {
+        let mut words = command_line.split_whitespace();
+        let program = words.next().ok_or( "empty engine command" )?;
+        let mut child = Process::new( program )
+            .args( words )
+            .stdin( Stdio::piped() )
+            .stdout( Stdio::piped() )
+            .spawn()
+            .map_err( |err| format!( "couldn't start {}: {}", program, err ) )?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, receiver) = channel();
+        std::thread::spawn( move || {
+            for line in BufReader::new( stdout ).lines() {
+                let Ok(line) = line else { break };
+                if let Some(reply) = Reply::parse( &line ) {
+                    if sender.send( reply ).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut engine = ExternalEngine {
+            name : program.to_string(),
+            child,
+            stdin,
+            replies : Mutex::new( receiver ),
+        };
+        engine.send( &Command::Engine )?;
+        Ok( engine )
+    }
+
+    pub fn send( &mut self, command : &Command ) -> Result<(), String> {
+        writeln!( self.stdin, "{}", command.to_text() ).map_err( |err| format!( "{} stopped listening: {}", self.name, err ) )
+    }
+
+    pub fn new_game( &mut self, pos : &Position ) -> Result<(), String> {
+        let mut layout = pos.snapshot;
+        set_stacks( &mut layout, &[] );
+        self.send( &Command::NewGame { seats : pos.seats, layout : Box::new( layout ) } )?;
+        self.send( &Command::Position { to_move : pos.to_move, stacks : stacks_of( &pos.snapshot ) } )
+    }
+
+    pub fn play( &mut self, seat : usize, mv : SplitMove ) -> Result<(), String> {
+        self.send( &Command::Move { seat, mv } )
+    }
+
+    pub fn go( &mut self, limits : SearchLimits ) -> Result<(), String> {
+        let movetime_ms = limits.time_budget.map( |budget| budget.as_millis() as u64 );
+        let depth = if movetime_ms.is_none() { Some( limits.max_depth ) } else { None };
+        self.send( &Command::Go { movetime_ms, depth } )
+    }
+
+    // The engine should answer with its best move so far
+    pub fn stop( &mut self ) -> Result<(), String> {
+        self.send( &Command::Stop )
+    }
+
+    // Some(Ok) once the engine has answered a go, Some(Err) if it has gone away
+    pub fn poll_best_move( &mut self ) -> Option<Result<Option<SplitMove>, String>> {
+        let replies = self.replies.get_mut().unwrap();
+        loop {
+            match replies.try_recv() {
+                Ok(Reply::BestMove(mv)) => return Some( Ok( mv ) ),
+                Ok(Reply::Id(name)) => self.name = name,
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some( Err( format!( "{} has quit", self.name ) ) ),
+            }
+        }
+    }
+}
+
+impl Drop for ExternalEngine {
+    fn drop( &mut self ) {
+        // Ask nicely, but don't wait around for an engine that isn't listening
+        let _ = self.send( &Command::Quit );
+        std::thread::sleep( std::time::Duration::from_millis( 50 ) );
+        if !matches!( self.child.try_wait(), Ok(Some(_)) ) {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
@@ -1,4 +1,6 @@
 //use std::slice::Iter;
+use rand::Rng;
+use rand::seq::SliceRandom;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum MapSpaceContents {
@@ -7,7 +9,7 @@ pub enum MapSpaceContents {
     Playable,  // A square that can be played on
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MapDirection {
     North,
     NorthEast,
@@ -22,6 +24,20 @@ impl MapDirection {
         [ MapDirection::North, MapDirection::NorthEast, MapDirection::SouthEast, 
         MapDirection::South, MapDirection::SouthWest, MapDirection::NorthWest ].iter().copied()
     }
+
+    // Turning clockwise, in the same order as iterator
+    pub fn next( &self ) -> MapDirection {
+        self.turn( 1 )
+    }
+
+    pub fn prev( &self ) -> MapDirection {
+        self.turn( 5 )
+    }
+
+    fn turn( &self, sixths : usize ) -> MapDirection {
+        let at = MapDirection::iterator().position( |dir| dir == *self ).unwrap();
+        MapDirection::iterator().nth( (at + sixths) % 6 ).unwrap()
+    }
 }
 
 impl Default for MapSpaceContents {
@@ -260,12 +276,148 @@ impl<'a> IntoIterator for &'a mut GameMap {
     }
 }
 
-pub fn gen_valid_moves( gamecurr : GameSnapshot, for_player : usize ) -> Vec<GameSnapshot>
+// Center of a hex in board units (hex size of 1), with the board centered on the origin.
+// Returns (x, z) to match the 3D view.
+pub fn hex_position( mapindex : i32 ) -> (f32, f32)
+{
+    let row : i32 = mapindex / (MAP_SZ as i32);
+    let col : i32 = mapindex % (MAP_SZ as i32);
+
+    // offset if col is odd
+    let sqrt3 = 3.0f32.sqrt();
+    let offset = if col % 2 == 1 { sqrt3 / 2.0 } else { 0.0 };
+    ((col as f32 - 4.5) * (3.0/2.0), (-row as f32 + 5.0) * sqrt3 + offset )
+}
+
+// Build a random board sized for the seats in use, with a 16 stack for each seat
+// somewhere along the edge
+pub fn generate_map( seats : u8, rng : &mut impl Rng ) -> GameSnapshot
+{
+    let mut snapshot = GameSnapshot::default();
+    let player_count = seats.count_ones() as i32;
+
+    // First, set up the map indices and build the map
+    let mut space_count = 0;
+    for (index, map_space) in snapshot.map.spaces.iter_mut().enumerate() {
+        map_space.ndx = index as i32;
+
+        let (x, z) = hex_position( map_space.ndx );
+
+        // this trims the board and makes it more rounder
+        if (x * x + z * z).sqrt() < 8.0 {
+            
+            // todo: replace this with adding some obstacles with preset shapes
+            if rng.gen_ratio(1, 8) {
+                map_space.contents = MapSpaceContents::Blocked;
+            } else {
+                map_space.contents = MapSpaceContents::Playable;
+                space_count += 1;                
+            }            
+        }
+    }
+
+    let target_spaces = player_count * 16;
+    let mut attempts = 1000;
+    while space_count > target_spaces && attempts > 0 {
+        // erode away the board edges
+        let edge_spaces = snapshot.map.edge_spaces_corners();
+                
+        let random_index = rng.gen_range(0..edge_spaces.len());
+        let selected_index = edge_spaces[random_index];        
+        
+        // Try removing this space
+        let mut map_copy = snapshot.map;
+        map_copy.spaces[selected_index as usize].contents = MapSpaceContents::NotInMap;
+
+        if map_copy.check_reachability() {    
+            snapshot.map = map_copy;
+            space_count -= 1;
+        }
+
+        attempts -= 1;
+    }
+
+    if attempts == 0 {
+        println!("Warning! Failed to erode map.");
+    }
+
+    // Find starting spaces
+    let mut edge_spaces = snapshot.map.edge_spaces();
+    edge_spaces.shuffle( rng );
+
+    for (i, start) in edge_spaces.iter().take( 4 ).enumerate() {
+        if seats & (1 << i) != 0 {
+            let selected_index = *start as usize;
+            snapshot.map.spaces[ selected_index ].player = (i+1) as u8;
+            snapshot.map.spaces[ selected_index ].power = 16;
+        }
+    }
+
+    snapshot
+}
+
+/// A single split: move `amount` power from the stack at `from` onto the empty space at `to`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SplitMove {
+    pub from : i32,
+    pub to : i32,
+    pub amount : u8,
+}
+
+impl GameSnapshot {
+    pub fn apply_move( &mut self, mv : SplitMove )
+    {
+        let player = self.map.spaces[ mv.from as usize ].player;
+        self.map.spaces[ mv.from as usize ].power -= mv.amount;
+        self.map.spaces[ mv.to as usize ].power += mv.amount;
+        self.map.spaces[ mv.to as usize ].player = player;
+    }
+
+    // Cheaper than gen_split_moves when we only care if the player is stuck
+    pub fn has_moves( &self, for_player : usize ) -> bool
+    {
+        for mapsq in &self.map {
+            if (mapsq.power > 1) && (mapsq.player == (for_player + 1) as u8) {
+                for mapdir in MapDirection::iterator() {
+                    let nbr = move_dir( mapsq.ndx, mapdir );
+                    if nbr != INVALID as i32 &&
+                       self.map.spaces[ nbr as usize ].contents == MapSpaceContents::Playable &&
+                       self.map.spaces[ nbr as usize ].power == 0 {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Zobrist-style hash of the stacks on the board. The board layout itself is not
+    // included since it never changes during a game.
+    pub fn hash( &self ) -> u64
+    {
+        let mut h = 0;
+        for mapsq in &self.map {
+            if mapsq.power > 0 {
+                h ^= splitmix64( ((mapsq.ndx as u64) << 16) | ((mapsq.player as u64) << 8) | (mapsq.power as u64) );
+            }
+        }
+        h
+    }
+}
+
+pub fn splitmix64( x : u64 ) -> u64
+{
+    let mut z = x.wrapping_add( 0x9E3779B97F4A7C15 );
+    z = (z ^ (z >> 30)).wrapping_mul( 0xBF58476D1CE4E5B9 );
+    z = (z ^ (z >> 27)).wrapping_mul( 0x94D049BB133111EB );
+    z ^ (z >> 31)
+}
+
+pub fn gen_split_moves( gamecurr : &GameSnapshot, for_player : usize ) -> Vec<SplitMove>
 {
     let mut result = Vec::new();
 
     // Find all the squares we could move from
-    let mut split_squares = Vec::new();
     for mapsq in &gamecurr.map {
         if (mapsq.power > 1) && (mapsq.player == (for_player + 1) as u8) {
             // This is our space, and we can potentially split here
@@ -274,80 +426,367 @@ pub fn gen_valid_moves( gamecurr : GameSnapshot, for_player : usize ) -> Vec<Gam
                 let move_ndx = gamecurr.map.search_dir( ndx, mapdir );
                 if move_ndx != ndx && move_ndx != INVALID as i32 {
                     // We can move in this direction
-                    split_squares.push( (ndx, move_ndx ));
+                    for amount in 1..mapsq.power {
+                        result.push( SplitMove { from : ndx, to : move_ndx, amount } );
+                    }
                 }
             }
         }
     }
 
-    for (start_ndx, move_ndx) in split_squares {
-        // can move from start_ndx to move_ndx                
-        let start_ndx = start_ndx as usize;
-        let move_ndx = move_ndx as usize;
+    result
+}
 
-        for amt in 1..gamecurr.map.spaces[start_ndx].power
-        {
-            let mut next : GameSnapshot = gamecurr;            
-            next.map.spaces[start_ndx].power -= amt;
-            next.map.spaces[move_ndx].power += amt;
-            next.map.spaces[move_ndx].player = (for_player + 1) as u8;
+pub fn gen_valid_moves( gamecurr : GameSnapshot, for_player : usize ) -> Vec<GameSnapshot>
+{
+    gen_split_moves( &gamecurr, for_player ).into_iter().map( |mv| {
+        let mut next : GameSnapshot = gamecurr;
+        next.apply_move( mv );
+        next
+    }).collect()
+}
+
+/// A snapshot plus whose turn it is, so we can look ahead past the current move.
+/// Seats with no moves are skipped over automatically, the game is over once
+/// nobody can move.
+#[derive(Copy, Clone, Debug)]
+pub struct Position {
+    pub snapshot : GameSnapshot,
+    pub to_move : usize,
+    pub seats : u8, // bitmask of the seats taking part
+}
 
-            result.push( next );
+impl Position {
+    pub fn new( snapshot : GameSnapshot, to_move : usize, seats : u8 ) -> Position
+    {
+        Position { snapshot, to_move, seats }
+    }
+
+    pub fn seat_count( &self ) -> i32
+    {
+        self.seats.count_ones() as i32
+    }
+
+    pub fn is_seated( &self, seat : usize ) -> bool
+    {
+        self.seats & (1 << seat) != 0
+    }
+
+    pub fn next_seat( &self, seat : usize ) -> usize
+    {
+        let mut next = seat;
+        for _ in 0..4 {
+            next = (next + 1) % 4;
+            if self.is_seated( next ) {
+                return next;
+            }
         }
+        seat
     }
 
-    result
+    pub fn is_game_over( &self ) -> bool
+    {
+        !(0..4).any( |seat| self.is_seated( seat ) && self.snapshot.has_moves( seat ) )
+    }
+
+    pub fn play( &self, mv : SplitMove ) -> Position
+    {
+        let mut next = *self;
+        next.snapshot.apply_move( mv );
+        next.advance();
+        next
+    }
+
+    // Pass the turn on to the next seat that is still able to move
+    pub fn advance( &mut self )
+    {
+        let first = self.next_seat( self.to_move );
+        let mut seat = first;
+        for _ in 0..4 {
+            if self.snapshot.has_moves( seat ) {
+                self.to_move = seat;
+                return;
+            }
+            seat = self.next_seat( seat );
+        }
+        // Nobody can move, game over
+        self.to_move = first;
+    }
+
+    pub fn hash( &self ) -> u64
+    {
+        self.snapshot.hash() ^ splitmix64( 0xABCD_0000 + self.to_move as u64 )
+    }
+}
+
+/// Where the tuner writes its weights and the game looks for them
+pub const WEIGHTS_FILE : &str = "ai_weights.txt";
+
+/// Knobs for evaluate_position. The defaults are the original hand tuned numbers,
+/// the AI personalities are just different sets of these.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EvalWeights {
+    pub stack : i32,                // flat value of every stack on the board
+    pub move_power : i32,           // per point of power a stack could still split off
+    pub reach : i32,                // value of the first empty hex along a ray
+    pub open_decay_pct : i32,       // falloff per hex along a ray only we can reach
+    pub contested_decay_pct : i32,  // falloff per hex an opponent could also reach
+    pub mobility_pct : i32,         // scales the blend of move power and opportunity
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        EvalWeights {
+            stack : 10000,
+            move_power : 10000,
+            reach : 10000,
+            open_decay_pct : 90,
+            contested_decay_pct : 50,
+            mobility_pct : 100,
+        }
+    }
+}
+
+impl EvalWeights {
+    pub const NAMES : [&'static str; 6] = [ "stack", "move_power", "reach", "open_decay_pct", "contested_decay_pct", "mobility_pct" ];
+
+    // Allowed range of each weight, same order as NAMES. Nothing goes negative, the
+    // decays stop making sense past 100%, and the rest stay small enough that
+    // evaluate_position and the personalities can't overflow.
+    pub const LIMITS : [(i32, i32); 6] = [ (0, 100_000), (0, 100_000), (0, 100_000), (0, 100), (0, 100), (0, 500) ];
+
+    // In the same order as NAMES, so the tuner can treat them as a plain vector
+    pub fn values( &self ) -> [i32; 6] {
+        [ self.stack, self.move_power, self.reach, self.open_decay_pct, self.contested_decay_pct, self.mobility_pct ]
+    }
+
+    pub fn from_values( v : [i32; 6] ) -> EvalWeights {
+        EvalWeights {
+            stack : v[0],
+            move_power : v[1],
+            reach : v[2],
+            open_decay_pct : v[3],
+            contested_decay_pct : v[4],
+            mobility_pct : v[5],
+        }
+    }
+
+    // Weights files are "name = value" lines, with # comments. Anything left out
+    // keeps its default value.
+    pub fn parse( text : &str ) -> Result<EvalWeights, String> {
+        let mut values = EvalWeights::default().values();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.split( '#' ).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((name, value)) = line.split_once( '=' ) else {
+                return Err( format!( "line {}: expected name = value", line_num + 1 ) );
+            };
+            let Some(field) = EvalWeights::NAMES.iter().position( |n| *n == name.trim() ) else {
+                return Err( format!( "line {}: unknown weight '{}'", line_num + 1, name.trim() ) );
+            };
+            let value : i32 = value.trim().parse().map_err( |_| format!( "line {}: bad number '{}'", line_num + 1, value.trim() ) )?;
+            let (low, high) = EvalWeights::LIMITS[field];
+            if !(low..=high).contains( &value ) {
+                return Err( format!( "line {}: {} must be from {} to {}", line_num + 1, name.trim(), low, high ) );
+            }
+            values[field] = value;
+        }
+        Ok( EvalWeights::from_values( values ) )
+    }
+
+    pub fn to_text( &self ) -> String {
+        EvalWeights::NAMES.iter().zip( self.values() ).map( |(name, value)| format!( "{} = {}\n", name, value ) ).collect()
+    }
+
+    pub fn load( path : &str ) -> Result<EvalWeights, String> {
+        let text = std::fs::read_to_string( path ).map_err( |err| format!( "{}: {}", path, err ) )?;
+        EvalWeights::parse( &text ).map_err( |err| format!( "{}: {}", path, err ) )
+    }
+
+    pub fn save( &self, path : &str ) -> Result<(), String> {
+        std::fs::write( path, self.to_text() ).map_err( |err| format!( "{}: {}", path, err ) )
+    }
+}
+
+pub fn evaluate_position( snap : GameSnapshot ) -> [i32; 4]
+{
+    evaluate_position_with( snap, &EvalWeights::default() )
+}
+
+pub fn evaluate_position_with( snap : GameSnapshot, weights : &EvalWeights ) -> [i32; 4]
+{
+    explain_position_with( snap, weights ).scores
+}
+
+/// What evaluate_position_with worked out on the way to its scores, for the AI debug overlay
+#[derive(Copy, Clone, Debug)]
+pub struct EvalBreakdown {
+    pub access_map : [i32; 100],     // bitmask of the players that can reach each hex with their next split
+    pub stack_weight : [i32; 100],   // what each stack added to its owner's score, 0 for empty hexes
+    pub scores : [i32; 4],
 }
 
-pub fn evaluate_position(snap:GameSnapshot) -> [i32;4]{
-    //let mut result = Vec::new();
-    let mut accessMap : [ i32 ; 100]=[0; 100];
-    let mut evalScore:[i32;4]=[0; 4];
-    for hex in &snap.map{
-        if hex.power>1{
-            let player=1<<(hex.player-1);
-            let index=hex.ndx;
+pub fn explain_position_with( snap : GameSnapshot, weights : &EvalWeights ) -> EvalBreakdown
+{
+    // Which players can reach each hex with their next split
+    let mut access_map : [ i32 ; 100] = [0; 100];
+    let mut stack_weight : [ i32 ; 100] = [0; 100];
+    let mut eval_score : [i32; 4] = [0; 4];
+    for hex in &snap.map {
+        if hex.power > 1 {
+            let player = 1 << (hex.player - 1);
             for mapdir in MapDirection::iterator() {
-                let targetIndex=snap.map.search_dir( index, mapdir );
-                if index != targetIndex{
-                    accessMap[targetIndex as usize]|=player;
+                let target_ndx = snap.map.search_dir( hex.ndx, mapdir );
+                if hex.ndx != target_ndx {
+                    access_map[target_ndx as usize] |= player;
                 }
             }
         }
     }
-    for hex in &snap.map{
-        if hex.power>0{
-            let mut weight:i32=10000;
-            if hex.power>1{
-                let player=1<<(hex.player-1);
-                let notPlayer=!player;
-                let index=hex.ndx;
-                let movepower:i32=((hex.power-1) as i32)*10000;
-                let mut opportunity:i32=0;
+
+    for hex in &snap.map {
+        if hex.power > 0 {
+            let mut weight : i32 = weights.stack;
+            if hex.power > 1 {
+                let not_player = !(1 << (hex.player - 1));
+                let movepower : i32 = ((hex.power - 1) as i32) * weights.move_power;
+                let mut opportunity : i32 = 0;
                 for mapdir in MapDirection::iterator() {
-                    let mut cHex=hex.ndx;
-                    let mut distancefactor:i32=10000;
-                    loop{
-                        cHex = move_dir( cHex, mapdir);
-                        if(cHex as usize == INVALID) || (snap.map.spaces[cHex as usize].contents != MapSpaceContents::Playable) || (snap.map.spaces[cHex as usize].power != 0){
+                    let mut curr = hex.ndx;
+                    let mut distancefactor : i32 = weights.reach;
+                    loop {
+                        curr = move_dir( curr, mapdir );
+                        if (curr as usize == INVALID) || 
+                           (snap.map.spaces[curr as usize].contents != MapSpaceContents::Playable) || 
+                           (snap.map.spaces[curr as usize].power != 0) {
                             break;
                         }
-                        if (accessMap[cHex as usize] & notPlayer)==0{
-                            distancefactor*=9;
-                        }
-                        else{
-                            distancefactor*=5;
+                        if (access_map[curr as usize] & not_player) == 0 {
+                            distancefactor *= weights.open_decay_pct;
+                        } else {
+                            distancefactor *= weights.contested_decay_pct;
                         }
-                        distancefactor/=10;
-                        opportunity+=distancefactor;
+                        distancefactor /= 100;
+                        opportunity += distancefactor;
                     }
                 }
-                if opportunity>0{
-                    weight+=1000000000/((1000000000/movepower)+(1000000000/opportunity));
+                if opportunity > 0 && movepower > 0 {
+                    // harmonic blend, a stack needs both power and room to be worth much
+                    let blend = 1000000000 / ((1000000000 / movepower) + (1000000000 / opportunity));
+                    weight += blend * weights.mobility_pct / 100;
                 }
             }
-            evalScore[(hex.player-1) as usize]+=weight;
+            stack_weight[hex.ndx as usize] = weight;
+            eval_score[(hex.player - 1) as usize] += weight;
+        }
+    }
+    EvalBreakdown { access_map, stack_weight, scores : eval_score }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rows and columns 2 to 7 to play on, plus a lone hex in the corner nothing can leave
+    fn test_board( stacks : &[(i32, u8, u8)] ) -> GameSnapshot {
+        let mut snapshot = GameSnapshot::default();
+        for (ndx, space) in snapshot.map.spaces.iter_mut().enumerate() {
+            let (row, col) = (ndx / MAP_SZ, ndx % MAP_SZ);
+            space.ndx = ndx as i32;
+            if ndx == 0 || ((2..8).contains( &row ) && (2..8).contains( &col )) {
+                space.contents = MapSpaceContents::Playable;
+            }
         }
+        for &(ndx, player, power) in stacks {
+            snapshot.map.spaces[ndx as usize].player = player;
+            snapshot.map.spaces[ndx as usize].power = power;
+        }
+        snapshot
     }
-    evalScore
-}
\ No newline at end of file
+
+    #[test]
+    fn next_seat_skips_empty_seats() {
+        let pos = Position::new( test_board( &[] ), 0, 0b1011 );
+        assert_eq!( pos.next_seat( 0 ), 1 );
+        assert_eq!( pos.next_seat( 1 ), 3 );
+        assert_eq!( pos.next_seat( 2 ), 3 );
+        assert_eq!( pos.next_seat( 3 ), 0 );
+
+        let alone = Position::new( test_board( &[] ), 2, 0b0100 );
+        assert_eq!( alone.next_seat( 2 ), 2 );
+    }
+
+    #[test]
+    fn advance_skips_stuck_and_empty_seats() {
+        // Seat 0 is boxed into the corner, seat 2 could move but isn't playing
+        let snapshot = test_board( &[ (0, 1, 5), (33, 2, 4), (44, 3, 6), (66, 4, 3) ] );
+        assert!( !snapshot.has_moves( 0 ) );
+
+        let mut pos = Position::new( snapshot, 1, 0b1011 );
+        pos.advance();
+        assert_eq!( pos.to_move, 3 );
+        pos.advance();
+        assert_eq!( pos.to_move, 1 );
+        assert!( !pos.is_game_over() );
+
+        // Only seat 1 can move, so it keeps the turn
+        let mut pos = Position::new( test_board( &[ (0, 1, 5), (33, 2, 4), (66, 4, 1) ] ), 1, 0b1011 );
+        pos.advance();
+        assert_eq!( pos.to_move, 1 );
+    }
+
+    #[test]
+    fn advance_with_nobody_left_to_move() {
+        let mut pos = Position::new( test_board( &[ (0, 1, 5), (33, 2, 1), (66, 4, 1) ] ), 1, 0b1011 );
+        assert!( pos.is_game_over() );
+        pos.advance();
+        assert_eq!( pos.to_move, 3 );
+    }
+
+    #[test]
+    fn play_hands_the_turn_on() {
+        let pos = Position::new( test_board( &[ (0, 1, 5), (33, 2, 4), (66, 4, 3) ] ), 1, 0b1011 );
+        let mv = gen_split_moves( &pos.snapshot, 1 )[0];
+        let next = pos.play( mv );
+        assert_eq!( next.to_move, 3 );
+        assert_eq!( next.snapshot.calc_simple_score( 1 ), 2 );
+    }
+
+    #[test]
+    fn weights_files_round_trip() {
+        let weights = EvalWeights { stack : 12345, open_decay_pct : 77, ..EvalWeights::default() };
+        assert_eq!( EvalWeights::parse( &weights.to_text() ), Ok( weights ) );
+        assert_eq!( EvalWeights::parse( "# nothing but a comment\n\n" ), Ok( EvalWeights::default() ) );
+    }
+
+    #[test]
+    fn weights_out_of_range_are_refused() {
+        for text in [ "stack = 2147483647", "move_power = -1", "open_decay_pct = 101", "mobility_pct = 100000", "reach = 1e9" ] {
+            assert!( EvalWeights::parse( text ).is_err(), "accepted '{}'", text );
+        }
+        for text in [ "stack = 100000", "contested_decay_pct = 0", "mobility_pct = 500" ] {
+            assert!( EvalWeights::parse( text ).is_ok(), "refused '{}'", text );
+        }
+    }
+
+    #[test]
+    fn the_largest_weights_dont_overflow() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        use crate::ai::Personality;
+
+        let largest = EvalWeights::from_values( EvalWeights::LIMITS.map( |(_, high)| high ) );
+        let mut rng = StdRng::seed_from_u64( 5 );
+        let mut pos = Position::new( generate_map( 0b1111, &mut rng ), 3, 0b1111 );
+        pos.advance();
+        while !pos.is_game_over() {
+            for personality in [ Personality::Balanced, Personality::Expander, Personality::Hoarder ] {
+                explain_position_with( pos.snapshot, &personality.adjust( largest ) );
+            }
+            let moves = gen_split_moves( &pos.snapshot, pos.to_move );
+            pos = pos.play( moves[rng.gen_range( 0..moves.len() )] );
+        }
+    }
+}
@@ -0,0 +1,278 @@
+// Learned evaluator.
+//
+// A linear model over a handful of board features for each seat, trained by
+// TD(lambda) from self-play (see bin/train.rs). A seat's value is in stacks; what
+// the model learns to predict is how far ahead of the average opponent the seat
+// finishes the game. The untrained model just counts stacks.
+//
+// Model files are "name = value" lines like the weights files, with # comments.
+// Anything left out keeps its untrained value.
+
+use crate::evaluator::Evaluator;
+use crate::gamestate::{move_dir, splitmix64, GameSnapshot, MapDirection, MapSpaceContents, INVALID, MAP_SZ};
+
+// Where the trainer writes its model and the game looks for it
+pub const MODEL_FILE : &str = "ai_model.txt";
+
+pub const FEATURE_COUNT : usize = 7;
+
+pub const FEATURE_NAMES : [&str; FEATURE_COUNT] = [
+    "stacks",         // stacks on the board, what the game is scored on
+    "spare_power",    // power above 1 on stacks that can still split
+    "stuck_power",    // power above 1 on stacks that are boxed in
+    "own_reach",      // empty hexes only this seat can split onto next
+    "shared_reach",   // empty hexes someone else can split onto as well
+    "room",           // empty hexes along this seat's open rays
+    "biggest_stack",  // spare power of the biggest stack
+];
+
+// Same units as evaluate_position, about this much per stack
+const SCALE : f32 = 10_000.0;
+
+pub type Features = [f32; FEATURE_COUNT];
+
+// Every seat's features, all zero for seats with nothing on the board
+pub fn features( snap : &GameSnapshot ) -> [Features; 4]
+{
+    let mut access_map = [0u8; MAP_SZ * MAP_SZ];  // who can split onto each hex next
+    let mut room_map = [0u8; MAP_SZ * MAP_SZ];    // whose rays run over each hex
+    let mut result = [[0.0; FEATURE_COUNT]; 4];
+
+    for hex in &snap.map {
+        if hex.power == 0 {
+            continue;
+        }
+        let seat = (hex.player - 1) as usize;
+        let bit = 1 << seat;
+        result[seat][0] += 1.0;
+        if hex.power < 2 {
+            continue;
+        }
+
+        let mut can_split = false;
+        for dir in MapDirection::iterator() {
+            let mut last = hex.ndx;
+            let mut curr = move_dir( hex.ndx, dir );
+            while curr != INVALID as i32 &&
+                  snap.map.spaces[curr as usize].contents == MapSpaceContents::Playable &&
+                  snap.map.spaces[curr as usize].power == 0 {
+                room_map[curr as usize] |= bit;
+                last = curr;
+                curr = move_dir( curr, dir );
+            }
+            if last != hex.ndx {
+                access_map[last as usize] |= bit;
+                can_split = true;
+            }
+        }
+
+        let spare = (hex.power - 1) as f32;
+        if can_split {
+            result[seat][1] += spare;
+        } else {
+            result[seat][2] += spare;
+        }
+        result[seat][6] = result[seat][6].max( spare );
+    }
+
+    for (access, room) in access_map.iter().zip( room_map ) {
+        for (seat, features) in result.iter_mut().enumerate() {
+            let bit = 1 << seat;
+            if access & bit != 0 {
+                if *access == bit {
+                    features[3] += 1.0;
+                } else {
+                    features[4] += 1.0;
+                }
+            }
+            if room & bit != 0 {
+                features[5] += 1.0;
+            }
+        }
+    }
+
+    result
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LearnedEval {
+    pub weights : Features,
+}
+
+impl Default for LearnedEval {
+    fn default() -> Self {
+        let mut weights = [0.0; FEATURE_COUNT];
+        weights[0] = 1.0;
+        LearnedEval { weights }
+    }
+}
+
+fn dot( a : &Features, b : &Features ) -> f32 {
+    a.iter().zip( b ).map( |(x, y)| x * y ).sum()
+}
+
+// A seat's features less the average of the other seats'. The model is linear, so
+// this is also the gradient of the seat's predicted margin.
+fn relative_features( all : &[Features; 4], seat : usize, seats : u8 ) -> Features {
+    let others : Vec<usize> = (0..4).filter( |s| *s != seat && seats & (1 << s) != 0 ).collect();
+    let mut result = all[seat];
+    for other in &others {
+        for (r, f) in result.iter_mut().zip( all[*other] ) {
+            *r -= f / others.len() as f32;
+        }
+    }
+    result
+}
+
+impl LearnedEval {
+    // In stacks
+    pub fn value( &self, features : &Features ) -> f32 {
+        dot( &self.weights, features )
+    }
+
+    // One pass of TD(lambda) over a finished game, `positions` running from the start
+    // to the final position. The last step is towards the real final margin. Steps
+    // are divided by the size of the gradient so big boards don't blow the weights up.
+    pub fn td_update( &mut self, positions : &[GameSnapshot], seats : u8, alpha : f32, lambda : f32 ) {
+        let Some(last) = positions.last() else { return };
+        let all : Vec<[Features; 4]> = positions.iter().map( features ).collect();
+
+        for seat in (0..4).filter( |s| seats & (1 << s) != 0 ) {
+            let others = (0..4).filter( |s| *s != seat && seats & (1 << s) != 0 );
+            let other_count = others.clone().count().max( 1 ) as f32;
+            let final_margin = last.calc_simple_score( seat as i32 ) as f32
+                - others.map( |s| last.calc_simple_score( s as i32 ) as f32 ).sum::<f32>() / other_count;
+
+            let mut trace = [0.0; FEATURE_COUNT];
+            for t in 0..positions.len() - 1 {
+                let gradient = relative_features( &all[t], seat, seats );
+                let now = self.value( &gradient );
+                let next = if t + 2 == positions.len() {
+                    final_margin
+                } else {
+                    self.value( &relative_features( &all[t + 1], seat, seats ) )
+                };
+
+                let norm = 1.0 + dot( &gradient, &gradient );
+                for (e, g) in trace.iter_mut().zip( gradient ) {
+                    *e = lambda * *e + g / norm;
+                }
+                let step = alpha * (next - now);
+                for (w, e) in self.weights.iter_mut().zip( trace ) {
+                    *w += step * e;
+                }
+            }
+        }
+    }
+
+    pub fn parse( text : &str ) -> Result<LearnedEval, String> {
+        let mut model = LearnedEval::default();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.split( '#' ).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((name, value)) = line.split_once( '=' ) else {
+                return Err( format!( "line {}: expected name = value", line_num + 1 ) );
+            };
+            let Some(feature) = FEATURE_NAMES.iter().position( |n| *n == name.trim() ) else {
+                return Err( format!( "line {}: unknown feature '{}'", line_num + 1, name.trim() ) );
+            };
+            model.weights[feature] = value.trim().parse().map_err( |_| format!( "line {}: bad number '{}'", line_num + 1, value.trim() ) )?;
+        }
+        Ok( model )
+    }
+
+    pub fn to_text( &self ) -> String {
+        FEATURE_NAMES.iter().zip( self.weights ).map( |(name, weight)| format!( "{} = {}\n", name, weight ) ).collect()
+    }
+
+    pub fn load( path : &str ) -> Result<LearnedEval, String> {
+        let text = std::fs::read_to_string( path ).map_err( |err| format!( "{}: {}", path, err ) )?;
+        LearnedEval::parse( &text ).map_err( |err| format!( "{}: {}", path, err ) )
+    }
+
+    pub fn save( &self, path : &str ) -> Result<(), String> {
+        std::fs::write( path, self.to_text() ).map_err( |err| format!( "{}: {}", path, err ) )
+    }
+}
+
+impl Evaluator for LearnedEval {
+    fn evaluate( &self, snap : &GameSnapshot ) -> [i32; 4] {
+        features( snap ).map( |f| (self.value( &f ) * SCALE) as i32 )
+    }
+
+    fn fingerprint( &self ) -> u64 {
+        self.weights.iter().fold( 0x1EA2_0000, |acc, w| splitmix64( acc ^ w.to_bits() as u64 ) )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::gamestate::{gen_split_moves, generate_map, Position};
+
+    // Random two player games, every position from the start to the end
+    fn random_games( count : u64 ) -> Vec<Vec<GameSnapshot>> {
+        (0..count).map( |seed| {
+            let mut rng = StdRng::seed_from_u64( seed );
+            let mut pos = Position::new( generate_map( 0b0011, &mut rng ), 3, 0b0011 );
+            pos.advance();
+            let mut positions = vec![ pos.snapshot ];
+            while !pos.is_game_over() {
+                pos = pos.play( *gen_split_moves( &pos.snapshot, pos.to_move ).choose( &mut rng ).unwrap() );
+                positions.push( pos.snapshot );
+            }
+            positions
+        }).collect()
+    }
+
+    // Mean squared gap between seat 0's predicted margin and the one it finished with
+    fn prediction_error( model : &LearnedEval, games : &[Vec<GameSnapshot>] ) -> f32 {
+        let mut total = 0.0;
+        let mut count = 0;
+        for positions in games {
+            let last = positions.last().unwrap();
+            let margin = (last.calc_simple_score( 0 ) - last.calc_simple_score( 1 )) as f32;
+            for snap in positions {
+                let predicted = model.value( &relative_features( &features( snap ), 0, 0b0011 ) );
+                total += (predicted - margin) * (predicted - margin);
+                count += 1;
+            }
+        }
+        total / count as f32
+    }
+
+    #[test]
+    fn model_files_round_trip() {
+        let model = LearnedEval { weights : [1.5, 0.25, -0.125, 0.0, 3.0, -2.75, 0.0625] };
+        assert_eq!( LearnedEval::parse( &model.to_text() ), Ok( model ) );
+
+        let partial = LearnedEval::parse( "# comment\nroom = 0.5\n" ).unwrap();
+        assert_eq!( partial.weights[0], 1.0 );
+        assert_eq!( partial.weights[5], 0.5 );
+
+        assert!( LearnedEval::parse( "reach = 1" ).is_err() );
+        assert!( LearnedEval::parse( "stacks = lots" ).is_err() );
+    }
+
+    #[test]
+    fn training_moves_predictions_towards_the_final_margin() {
+        let games = random_games( 8 );
+        let mut model = LearnedEval::default();
+        let before = prediction_error( &model, &games );
+
+        for _ in 0..5 {
+            for positions in &games {
+                model.td_update( positions, 0b0011, 0.05, 0.7 );
+            }
+        }
+
+        assert_ne!( model, LearnedEval::default() );
+        assert!( prediction_error( &model, &games ) < before );
+    }
+}
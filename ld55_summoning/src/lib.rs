@@ -0,0 +1,18 @@
+//! Game rules and computer players, kept free of Bevy so the headless tools can use them.
+
+pub mod gamestate;
+pub mod evaluator;
+pub mod learned;
+pub mod movegen;
+pub mod ai;
+pub mod search;
+pub mod multisearch;
+pub mod mcts;
+pub mod arena;
+pub mod analysis;
+pub mod rounds;
+pub mod clock;
+pub mod endgame;
+pub mod book;
+pub mod protocol;
+pub mod external;
@@ -5,22 +5,37 @@ use bevy::{
         //bloom::{BloomCompositeMode, BloomSettings},
         bloom::BloomSettings,
         tonemapping::Tonemapping,
-    }, input::keyboard::Key, pbr::NotShadowCaster, prelude::*, render::{mesh::VertexAttributeValues, texture::{ImageAddressMode, ImageSamplerDescriptor}}, window::WindowResized
+    }, input::mouse::{MouseScrollUnit, MouseWheel}, pbr::NotShadowCaster, prelude::*, render::{mesh::VertexAttributeValues, texture::{ImageAddressMode, ImageSamplerDescriptor}}, window::WindowResized, ui::RelativeCursorPosition
 
 };
 
 
-use gamestate::gen_valid_moves;
-use gamestate::evaluate_position;
-use rand::Rng;
+use ld55_summoning::gamestate;
+use gamestate::{explain_position_with, gen_split_moves, EvalWeights};
+use ld55_summoning::search::{SearchLimits, SearchResult, StopFlag, WIN_SCORE};
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::tasks::{block_on, poll_once, AsyncComputeTaskPool, Task};
+use ld55_summoning::ai::{greedy_move, AIEngines, AIPolicy, Difficulty};
+use ld55_summoning::external::ExternalEngine;
+#[cfg(not(target_arch = "wasm32"))]
+use ld55_summoning::book::OpeningBook;
+#[cfg(not(target_arch = "wasm32"))]
+use ld55_summoning::learned::{LearnedEval, MODEL_FILE};
+use gamestate::SplitMove;
+use ld55_summoning::analysis::{Judgement, MoveReview, Reviewer, STACK_SCORE};
+use ld55_summoning::protocol::{move_text, turn_text};
+use ld55_summoning::rounds::{score_round, RoundScore};
+use ld55_summoning::clock::Clocks;
 use rand::seq::SliceRandom;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use bevy::render::view::RenderLayers;
 
 //use std::collections::HashSet;
 use std::{f32::consts::PI, time::Duration};
 
-use crate::gamestate::{GameSnapshot, MapDirection, INVALID};
-use crate::gamestate::MapSpaceContents;
-pub mod gamestate;
+use gamestate::{GameSnapshot, MapDirection, Position, INVALID};
+use gamestate::MapSpaceContents;
 
 const HEX_SZ : f32 = 1.0;
 
@@ -32,10 +47,11 @@ const HEX_SZ : f32 = 1.0;
 //     // todo: card stats, etc 
 // }
 
-#[derive(Default, PartialEq)]
+#[derive(Default, PartialEq, Clone, Copy)]
 enum PlayerType {
     Local,
-    AI, // AI(AIPolicy)
+    AI(AIPolicy),
+    External(u8),  // one of the --engine programs
     #[default]
     NotActive
 }
@@ -46,6 +62,7 @@ struct PlayerStuff
     color: Color,
     color2 : Color,
     ring_mtl: [ Handle<StandardMaterial>; 21 ],
+    ghost_mtl: [ Handle<StandardMaterial>; 21 ],  // see-through, for previewing a split
     ptype : PlayerType,
     out_of_moves : bool,
 }
@@ -63,12 +80,42 @@ enum GameStateChanged {
     CircleSplit(i32,i32),  // old ndx -> new ndx
 }
 
+// What a seat did with its turn, sent by the input and the AI. The turn manager
+// plays it and moves the game on.
 #[derive(Event)]
-struct TurnAdvance(i32); 
+struct TurnTaken {
+    seat : usize,
+    mv : Option<SplitMove>,  // None for a pass
+}
+
+// Sent by the turn manager when it's a seat's turn to move
+#[derive(Event)]
+struct TurnAdvance(i32);
+
+// Sent by the turn manager once every seat has had a turn
+#[derive(Event)]
+struct RoundEnded;
+
+// Sent by the turn manager when nobody can move any more
+#[derive(Event)]
+struct GameOver {
+    winners : Vec<usize>,  // more than one for a tie
+}
 
 #[derive(Event)]
 struct PlayerSettingsChanged; 
 
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum AppState {
+    #[default]
+    Title,
+    Setup,     // choosing who plays each seat
+    Playing,
+    RoundScoring,  // play waits while the round's scores go up
+    Paused,
+    GameOver,  // the final board, and the review
+}
+
 // FIXME: this should be a singleton component and not a resource
 #[derive(Resource)]
 struct GameState {
@@ -78,7 +125,11 @@ struct GameState {
     player_count : i32,
     player_turn : i32,
     turn_num : i32,    
-    round_scoring_finished : bool,
+    bonus_points : [i32; 4],            // from round scoring, to settle ties
+    start : GameSnapshot,               // the board as it was dealt
+    history : Vec<(usize, SplitMove)>,  // every move played, with the seat that played it
+    log : Vec<(usize, Option<SplitMove>)>,  // every turn, passes too
+    game_over : bool,
 }
 
 impl Default for GameState {
@@ -89,11 +140,44 @@ impl Default for GameState {
             player_count: 0,
             player_turn: 0,
             turn_num: 0,
-            round_scoring_finished : true,
+            bonus_points: [0; 4],
+            start : GameSnapshot::default(),
+            history : Vec::new(),
+            log : Vec::new(),
+            game_over : false,
         }
     }
 }
 
+// Tallied up over a match
+#[derive(Resource, Default)]
+struct MatchStats {
+    hints_used : [i32; 4],
+}
+
+// Post-game analysis, started with A once the game is over
+#[derive(Resource, Default)]
+struct Review {
+    moves : Vec<MoveReview>,
+    viewing : Option<usize>,  // the move being looked at, None for the final position
+    final_snapshot : GameSnapshot,
+    progress : Arc<AtomicUsize>,
+    #[cfg(not(target_arch = "wasm32"))]
+    task : Option<Task<Vec<MoveReview>>>,
+}
+
+// F3 shows how the evaluator sees the board, and what the computer player
+// thought of its options last time it moved
+#[derive(Resource, Default)]
+struct AIDebug {
+    shown : bool,
+    last_thought : Option<(usize, AIPolicy, SearchResult)>,
+}
+
+// The eval graph is drawn over the 2D camera only
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct ReviewGraphGizmos;
+
 #[derive(Component)]
 struct Ground;
 
@@ -106,8 +190,22 @@ struct PlayerHelp;
 #[derive(Component)]
 struct RoundScoringFrame;
 
+// Everything on a screen is tagged with its marker and despawned on the way out
+#[derive(Component)]
+struct TitleScreen;
+
+#[derive(Component)]
+struct SetupScreen;
+
+// Tapped or clicked to start the game from the setup screen
+#[derive(Component)]
+struct StartButton;
+
+// A line of the setup screen that was tapped, and which one
+type SetupTap = (&'static Interaction, Option<&'static PlayerSetting>, Has<TimeSetting>, Has<StartButton>);
+
 #[derive(Component)]
-struct TitleScreenCrap;
+struct PauseScreen;
 
 #[derive(Component)]
 struct RoundIcon(i32);
@@ -118,9 +216,48 @@ struct TurnIcon(i32);
 #[derive(Component)]
 struct PlayerScore(i32);
 
+// A line of the round's results, flying from the middle of the screen into the frame
+#[derive(Component)]
+struct RoundScorePopup {
+    target : Vec3,
+}
+
+// Everyone's time, set up on the setup screen and wound back for each game
+#[derive(Resource, Default)]
+struct MatchClocks(Clocks);
+
+// The seat picked with the d-pad on the setup screen, once a controller is used there
+#[derive(Resource, Default)]
+struct PadSeat(Option<usize>);
+
+// The round being scored
+#[derive(Resource, Default)]
+struct RoundScoreboard {
+    score : RoundScore,
+    elapsed : f32,
+}
+
 #[derive(Component)]
 struct PlayerSetting(i32);
 
+#[derive(Component)]
+struct TimeSetting;
+
+// Time left, next to the score
+#[derive(Component)]
+struct PlayerClock(i32);
+
+// The move list, scrolled with the mouse wheel over it or PageUp/PageDown
+#[derive(Component)]
+struct GameLog;
+
+#[derive(Component, Default)]
+struct GameLogText {
+    position : f32,  // how far the text is scrolled up, in pixels
+    follow : bool,   // keeping up with the latest move
+    shown : usize,   // turns in the text
+}
+
 
 #[derive(Component)]
 struct CircleAnimator {
@@ -134,6 +271,10 @@ struct GameCursor {
     drag_from : Option<usize>,
     _drag_dest : Option<usize>,
     split_pct : f32,
+    dir : MapDirection,  // which way the stack being dragged splits
+    amount : i32,        // and how much of it goes
+    amount_set : bool,   // picked with the wheel or keys, the drag distance no longer counts
+    buttons : bool,      // steered with the keys or a controller rather than the mouse
 }
 
 #[derive(Component)]
@@ -141,11 +282,290 @@ struct SplitLabel {
     is_dest : bool
 }
 
+// The stacks a split would leave behind, shown before it's played
+#[derive(Component)]
+struct SplitGhost {
+    is_dest : bool
+}
+
+// Evaluator weight over each stack, for the AI debug overlay
+#[derive(Component)]
+struct DebugStackLabel(usize);
+
+#[derive(Component)]
+struct DebugPanel;
+
+type AIThought = (AIEngines, SearchResult);
+
+// The opening book from the book tool, picked up from the working directory if
+// present like the tuner's weights and the trainer's model
+#[cfg(not(target_arch = "wasm32"))]
+const OPENING_BOOK_FILE : &str = "opening_book.txt";
+
+// Longest the AI may think in the browser, where the search holds up the frame
+#[cfg(target_arch = "wasm32")]
+const WASM_THINK_BUDGET : Duration = Duration::from_millis( 250 );
+
 #[derive(Component)]
 struct AIController {
     turn_timer: Timer,
+    for_turn : i32,  // the turn it's thinking about
+    due : bool,  // handed a turn it hasn't started on yet
+    engines : Option<AIEngines>, // handed to the thinking task while it runs
+    limits : SearchLimits,
+    stop : StopFlag,
+    base_weights : EvalWeights,  // a copy for the debug overlay, the engines may be off thinking
+    #[cfg(not(target_arch = "wasm32"))]
+    thinking : Option<Task<AIThought>>,
+    // No worker threads on the web, the move is worked out on the spot instead
+    #[cfg(target_arch = "wasm32")]
+    thinking : Option<AIThought>,
+}
+
+// Runs the AI on behalf of a human player who asks for a hint
+#[derive(Component)]
+struct HintFinder {
+    search : AIController,
+    for_turn : i32,
+    suggestion : Option<SplitMove>,
+}
+
+impl AIController {
+    fn new( limits : SearchLimits ) -> AIController {
+        #[allow(unused_mut)]
+        let mut engines = AIEngines::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        if std::path::Path::new( gamestate::WEIGHTS_FILE ).exists() {
+            match EvalWeights::load( gamestate::WEIGHTS_FILE ) {
+                Ok(weights) => {
+                    println!("Using AI weights from {}", gamestate::WEIGHTS_FILE );
+                    engines.base_weights = weights;
+                }
+                Err(err) => println!("Couldn't load AI weights, {}", err ),
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if std::path::Path::new( MODEL_FILE ).exists() {
+            match LearnedEval::load( MODEL_FILE ) {
+                Ok(model) => {
+                    println!("Using the learned model from {}", MODEL_FILE );
+                    engines.model = Some( Arc::new( model ) );
+                }
+                Err(err) => println!("Couldn't load the learned model, {}", err ),
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if std::path::Path::new( OPENING_BOOK_FILE ).exists() {
+            match OpeningBook::load( OPENING_BOOK_FILE ) {
+                Ok(book) => {
+                    println!("Using the opening book from {}, {} positions", OPENING_BOOK_FILE, book.len() );
+                    engines.book = Some( Arc::new( book ) );
+                }
+                Err(err) => println!("Couldn't load the opening book, {}", err ),
+            }
+        }
+        AIController {
+            turn_timer : Timer::new(Duration::from_secs_f32( 3.0 ), TimerMode::Once),
+            for_turn : 0,
+            due : false,
+            stop : engines.stop.clone(),
+            base_weights : engines.base_weights,
+            engines : Some( engines ),
+            limits,
+            thinking : None,
+        }
+    }
+
+    fn is_thinking( &self ) -> bool {
+        self.thinking.is_some()
+    }
+
+    fn start_thinking( &mut self, policy : AIPolicy, pos : Position, limits : SearchLimits ) {
+        let mut engines = self.engines.take().unwrap_or_else( || {
+            let mut engines = AIEngines { base_weights : self.base_weights, ..default() };
+            engines.set_stop( self.stop.clone() );
+            engines
+        });
+        self.stop.reset();
+
+        // The browser has no threads to think on, so keep it short to not freeze the page
+        #[cfg(target_arch = "wasm32")]
+        let limits = SearchLimits {
+            time_budget : Some( limits.time_budget.map_or( WASM_THINK_BUDGET, |budget| budget.min( WASM_THINK_BUDGET ) ) ),
+            ..limits
+        };
+
+        let think = move || {
+            let mut rng = rand::thread_rng();
+            let result = engines.choose_move( policy, &pos, limits, &mut rng );
+            (engines, result)
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.thinking = Some( AsyncComputeTaskPool::get().spawn( async move { think() } ) );
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.thinking = Some( think() );
+        }
+    }
+
+    // The result once the thinking is done, without blocking
+    fn poll_thinking( &mut self ) -> Option<SearchResult> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let thought = block_on( poll_once( self.thinking.as_mut()? ) );
+        #[cfg(target_arch = "wasm32")]
+        let thought = self.thinking.take();
+
+        let (engines, result) = thought?;
+        self.thinking = None;
+        self.engines = Some( engines );
+        Some( result )
+    }
+
+    // Ask the search to stop and play the best move it has found so far
+    fn hurry( &self ) {
+        self.stop.stop();
+    }
+
+    // Forget the game it was playing, waiting for any search still going to stop
+    fn cancel( &mut self ) {
+        self.hurry();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(task) = self.thinking.take() {
+            self.engines = Some( block_on( task ).0 );
+        }
+        #[cfg(target_arch = "wasm32")]
+        if let Some((engines, _)) = self.thinking.take() {
+            self.engines = Some( engines );
+        }
+        if let Some(engines) = self.engines.as_mut() {
+            engines.clear();
+        }
+        self.due = false;
+        self.turn_timer = Timer::new(Duration::from_secs_f32( 3.0 ), TimerMode::Once);
+    }
+}
+
+
+// Engine programs given with --engine, and the ones running for the current game
+#[derive(Resource, Default)]
+struct EngineSeats {
+    commands : Vec<String>,
+    running : [Option<ExternalEngine>; 4],
+    sent : usize,  // how much of the game history they have been told about
+    thinking : bool,
+    late : [bool; 4],  // seats whose answer is to be thrown away, they ran out of time
 }
 
+impl EngineSeats {
+    fn from_args() -> EngineSeats {
+        let mut seats = EngineSeats::default();
+        let mut args = std::env::args().skip( 1 );
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--engine" => match args.next() {
+                    Some(command) => seats.commands.push( command ),
+                    None => println!("--engine needs a command line after it" ),
+                },
+                _ => println!("Ignoring argument {}, the only option is --engine \"<command line>\"", arg ),
+            }
+        }
+        seats
+    }
+
+    // Just the program name, without the path or arguments
+    fn label( &self, index : u8 ) -> String {
+        let command = self.commands.get( index as usize ).map( String::as_str ).unwrap_or( "?" );
+        let program = command.split_whitespace().next().unwrap_or( "?" );
+        std::path::Path::new( program ).file_name().map_or( program.to_string(), |name| name.to_string_lossy().into_owned() )
+    }
+
+    // Start an engine for each seat that has one. Seats whose engine won't start
+    // get the default AI instead, returns true if that happened.
+    fn start_game( &mut self, stuff : &mut GoodStuff, pos : &Position ) -> bool {
+        self.running = Default::default();
+        self.sent = 0;
+        self.thinking = false;
+        self.late = [false; 4];
+
+        let mut replaced = false;
+        for seat in 0..4 {
+            if let PlayerType::External(index) = stuff.player_stuff[seat].ptype {
+                let started = ExternalEngine::launch( &self.commands[index as usize] )
+                    .and_then( |mut engine| engine.new_game( pos ).map( |_| engine ) );
+                match started {
+                    Ok(engine) => self.running[seat] = Some( engine ),
+                    Err(err) => {
+                        println!("Player {} gets the built-in AI instead, {}", seat + 1, err );
+                        stuff.player_stuff[seat].ptype = PlayerType::AI( AIPolicy::default() );
+                        replaced = true;
+                    }
+                }
+            }
+        }
+        replaced
+    }
+
+    // Pass on any moves made since last time to every engine
+    fn send_moves( &mut self, history : &[(usize, SplitMove)] ) {
+        for slot in &mut self.running {
+            let Some(engine) = slot else { continue };
+            for (seat, mv) in &history[self.sent..] {
+                if let Err(err) = engine.play( *seat, *mv ) {
+                    println!("{}", err );
+                    *slot = None;
+                    break;
+                }
+            }
+        }
+        self.sent = history.len();
+    }
+
+    fn go( &mut self, seat : usize, limits : SearchLimits ) {
+        if let Some(engine) = &mut self.running[seat] {
+            if let Err(err) = engine.go( limits ) {
+                println!("{}", err );
+                self.running[seat] = None;
+            }
+        }
+        self.thinking = true;
+    }
+
+    fn hurry( &mut self, seat : usize ) {
+        if let Some(engine) = &mut self.running[seat] {
+            let _ = engine.stop();
+        }
+    }
+
+    // Stop waiting for the seat's move, whatever it comes up with is ignored
+    fn abandon( &mut self, seat : usize ) {
+        self.hurry( seat );
+        self.late[seat] = self.running[seat].is_some();
+        self.thinking = false;
+    }
+
+    fn poll( &mut self, seat : usize ) -> Option<Result<Option<SplitMove>, String>> {
+        if self.late[seat] {
+            if let Some(engine) = &mut self.running[seat] {
+                engine.poll_best_move()?.ok();
+            }
+            self.late[seat] = false;
+            return None;
+        }
+
+        let answer = match &mut self.running[seat] {
+            Some(engine) => engine.poll_best_move()?,
+            None => Err( "the engine isn't running".to_string() ),
+        };
+        if answer.is_err() {
+            self.running[seat] = None;
+        }
+        self.thinking = false;
+        Some( answer )
+    }
+}
 
 #[derive(Component)]
 struct MapSpaceVisual 
@@ -177,19 +597,54 @@ fn main() {
         //.insert_resource( CardDeck::default() )
         .insert_resource( GoodStuff::default() )
         .insert_resource( GameState::default() )
+        .init_resource::<MatchStats>()
+        .insert_resource( EngineSeats::from_args() )
+        .init_resource::<Review>()
+        .init_resource::<AIDebug>()
+        .init_resource::<RoundScoreboard>()
+        .init_resource::<MatchClocks>()
+        .init_resource::<PadSeat>()
+        .init_gizmo_group::<ReviewGraphGizmos>()
+        .init_state::<AppState>()
         .add_systems(Startup, setup)
-        //.add_systems(Startup, build_map )                                
-        .add_systems(Update, build_map )                                
-        .add_systems( Update, handle_input )
+        .add_systems(Startup, setup_ai_debug)
+        .add_systems( OnEnter( AppState::Title ), spawn_title )
+        .add_systems( OnExit( AppState::Title ), despawn_screen::<TitleScreen> )
+        .add_systems( Update, title_input.run_if( in_state( AppState::Title ) ) )
+        .add_systems( OnEnter( AppState::Setup ), spawn_setup )
+        .add_systems( OnExit( AppState::Setup ), despawn_screen::<SetupScreen> )
+        .add_systems( Update, (setup_input, player_settings).run_if( in_state( AppState::Setup ) ) )
+        .add_systems( OnTransition { from : AppState::Setup, to : AppState::Playing }, build_map )
+        .add_systems( OnTransition { from : AppState::GameOver, to : AppState::Playing }, build_map )
+        .add_systems( OnExit( AppState::GameOver ), (teardown_game, reset_game_ui) )
+        .add_systems( OnTransition { from : AppState::Paused, to : AppState::Setup }, (teardown_game, reset_game_ui) )
+        .add_systems( Update, (
+            handle_input,
+            button_play.after( handle_input ),
+            draw_split_feedback,
+            update_ai,
+            update_clocks,
+            turn_manager.after( button_play ).after( update_ai ).after( update_clocks ),
+            follow_turns.after( turn_manager ),
+            show_ai_thinking,
+            update_hint,
+        ).run_if( in_state( AppState::Playing ) ) )
+        .add_systems( OnEnter( AppState::RoundScoring ), start_round_scoring )
+        .add_systems( OnExit( AppState::RoundScoring ), despawn_screen::<RoundScorePopup> )
+        .add_systems( Update, update_round_scoring.run_if( in_state( AppState::RoundScoring ) ) )
+        .add_systems( OnEnter( AppState::Paused ), spawn_pause )
+        .add_systems( OnExit( AppState::Paused ), despawn_screen::<PauseScreen> )
+        .add_systems( Update, pause_input.before( button_play ).run_if( in_state( AppState::Playing ).or_else( in_state( AppState::Paused ) ) ) )
+        .add_systems( Update, (update_review, draw_review, game_over_input).run_if( in_state( AppState::GameOver ) ) )
+        .add_systems( Update, (player_guidance, hand_out_turns, draw_ai_debug, update_game_log, update_split_ghosts, touch_camera).run_if( in_game ) )
         .add_systems( Update, on_gamestate_changed )
-        .add_systems( Update, draw_split_feedback )
-        .add_systems( Update, player_guidance )
-        .add_systems( Update, update_ai )
         .add_systems( Update, update_circ_anim )
         .add_systems( Update, update_ui )
-        .add_systems( Update, player_settings )
         .add_event::<GameStateChanged>()
+        .add_event::<TurnTaken>()
         .add_event::<TurnAdvance>()
+        .add_event::<RoundEnded>()
+        .add_event::<GameOver>()
         .add_event::<PlayerSettingsChanged>()
         .run();
 }
@@ -203,7 +658,6 @@ fn setup(
     //mut cards: ResMut<CardDeck>,
     mut stuff: ResMut<GoodStuff>,
     mut config_store: ResMut<GizmoConfigStore>,
-    mut ev_settings: EventWriter<PlayerSettingsChanged>,
     //game: Res<GameState>,
     asset_server: Res<AssetServer>
 ) {
@@ -212,6 +666,8 @@ fn setup(
     // set up gizmos
     let (config, _) = config_store.config_mut::<DefaultGizmoConfigGroup>();
     config.line_width *= 2.0;
+    let (config, _) = config_store.config_mut::<ReviewGraphGizmos>();
+    config.render_layers = RenderLayers::layer( 1 );
 
 
     // MUUUUSSSIICC 
@@ -298,7 +754,14 @@ fn setup(
                 ..default()
             };
             
+            let ghost_mtl = StandardMaterial {
+                base_color: color_support.with_a( 0.35 ),
+                emissive: color_main * 0.3,
+                ..ring_mtl.clone()
+            };
+
             stuff.player_stuff[p].ring_mtl[i - 1] = materials.add(ring_mtl);
+            stuff.player_stuff[p].ghost_mtl[i - 1] = materials.add(ghost_mtl);
         }
     }
         
@@ -317,6 +780,7 @@ fn setup(
     // cursor with no cube
     commands.spawn((GameCursor { ndx : 0, 
             drag_from : None, _drag_dest : None, cursor_world : Vec3::ZERO, split_pct : 0.5,
+            dir : MapDirection::North, amount : 0, amount_set : false, buttons : false,
             }, Transform::default() ));
     
     // light
@@ -353,8 +817,7 @@ fn setup(
                 hdr: true,
                 ..default()
             },
-            transform: Transform::from_xyz( 0.0, 15.0, 12.0)
-                                    .looking_at( Vec3 { x:0.0, y: 0.0, z : 3.0 }, Vec3::Y),
+            transform: camera_home(),
             tonemapping: Tonemapping::TonyMcMapface,         
             ..default()
             },
@@ -413,21 +876,32 @@ fn setup(
             SplitLabel { is_dest : false },
         ));
 
+    for is_dest in [ false, true ] {
+        commands.spawn((PbrBundle {
+            mesh: stuff.ring_mesh.clone(),
+            visibility: Visibility::Hidden,
+            ..default()
+        }, NotShadowCaster, SplitGhost { is_dest } ));
+    }
+
 
-    commands.spawn( AIController {
-        turn_timer : Timer::new(Duration::from_secs_f32( 3.0 ), TimerMode::Once),        
+    commands.spawn( AIController::new( SearchLimits::default() ) );
+    commands.spawn( HintFinder {
+        search : AIController::new( SearchLimits::default() ),
+        for_turn : 0,
+        suggestion : None,
     });
 
 
     // 2D scene -------------------------------
-    commands.spawn(Camera2dBundle { 
+    commands.spawn((Camera2dBundle { 
         camera: Camera {
             hdr: true,
             order: 2, // Draw sprites on top of 3d world
             ..default()
         },
         ..default()
-    });
+    }, RenderLayers::from_layers( &[0, 1] ) ));
 
     // Load card atlas
     // let texture = asset_server.load("cardfish_cards.png");
@@ -454,42 +928,12 @@ fn setup(
     //     ..default()
     // });
 
-    commands.spawn((SpriteBundle {
-        texture: asset_server.load("title.png"),
-        transform: Transform::from_xyz( 0.0, 0.0, 3.0 ).with_scale( Vec3::splat( 0.6)),
-        ..default()
-    }, TitleScreenCrap ));
-
     // setup player status
     stuff.player_stuff[0].ptype = PlayerType::Local;
-    stuff.player_stuff[1].ptype = PlayerType::AI;
-    stuff.player_stuff[2].ptype = PlayerType::AI;
+    stuff.player_stuff[1].ptype = PlayerType::AI( AIPolicy::default() );
+    stuff.player_stuff[2].ptype = PlayerType::AI( AIPolicy::default() );
     stuff.player_stuff[3].ptype = PlayerType::NotActive;
 
-    let mut yy = 440.0;
-    for i in 0..4 {
-        
-            commands.spawn((
-                TextBundle::from_section("Player # -- ???",
-                    TextStyle {
-                        color: stuff.player_stuff[i].color,
-                        font_size: 30.,
-                        ..default()
-                    },
-                )
-                .with_style(Style {
-                    position_type: PositionType::Absolute,
-                    top: Val::Px(yy),
-                    left: Val::Px( 550.0),                
-                    ..default()
-                }),                
-                PlayerSetting(i as i32),
-                TitleScreenCrap) );
-            yy += 30.0;        
-    }
-
-    ev_settings.send( PlayerSettingsChanged );
-    
 
     commands.spawn((SpriteBundle {
         texture: asset_server.load("turn_frame.png"),
@@ -540,76 +984,432 @@ fn setup(
                 PlayerScore( i as i32),
             ));
 
+            commands.spawn((
+                TextBundle::from_section("",
+                    TextStyle {
+                        color: stuff.player_stuff[i].color,
+                        font_size: 24.,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(xx + 12.0),
+                    left: Val::Px(140.0),
+                    ..default()
+                }),
+                PlayerClock( i as i32),
+            ));
+
             xx += 50.0;
         //}
     }
+
+    commands.spawn(( NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(xx + 20.0),
+            left: Val::Px(12.0),
+            width: Val::Px(240.0),
+            height: Val::Px(260.0),
+            overflow: Overflow::clip_y(),
+            ..default()
+        },
+        visibility: Visibility::Hidden,
+        ..default()
+    }, GameLog, RelativeCursorPosition::default() )).with_children(|parent| {
+        parent.spawn(( TextBundle::from_section("",
+            TextStyle {
+                font_size: 18.,
+                ..default()
+            }),
+            GameLogText { follow : true, ..default() },
+        ));
+    });
     
 
 }
 
 
-// fn test_start_game ( 
-//     // mut world : &mut World,
-//     mut commands: Commands,            
-//     keyboard_input: Res<ButtonInput<KeyCode>>,
-// )
-// {
-//     if keyboard_input.just_pressed( KeyCode::KeyW ) {
-//         println!("W pressed");
-//         //world.run_system_once(build_map);
-//     }
-// }
+fn spawn_title_art( commands : &mut Commands, asset_server : &AssetServer, marker : impl Component )
+{
+    commands.spawn((SpriteBundle {
+        texture: asset_server.load("title.png"),
+        transform: Transform::from_xyz( 0.0, 0.0, 3.0 ).with_scale( Vec3::splat( 0.6)),
+        ..default()
+    }, marker ));
+}
 
-// world.run_system_once(count_entities);
-// fn spawn_cards ( 
-//     mut commands: Commands,        
-//     cards: Res<CardDeck>,
-//     keyboard_input: Res<ButtonInput<KeyCode>>,
-// )
-// {
-//     if keyboard_input.just_pressed( KeyCode::KeyW ) {
-//         println!("W pressed");
-//         let mut rng = rand::thread_rng();
-//         commands.spawn((
-//             SpriteSheetBundle {
-//                 texture: cards.texture.clone(),
-//                 atlas: TextureAtlas {
-//                     layout: cards.layout.clone(),
-//                     index: rng.gen_range(1..20),
-//                 },                     
-//                 transform: Transform::from_xyz(rng.gen::<f32>() * 1000.0 - 500.0, rng.gen::<f32>() * 700.0 - 350.0, 0.0),
-//                 ..default()
-//             },        
-//         ));
-//     }
-// }
+fn spawn_title(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>
+) {
+    spawn_title_art( &mut commands, &asset_server, TitleScreen );
 
+    commands.spawn((
+        TextBundle::from_section("Enter or tap: Choose players",
+            TextStyle {
+                font_size: 30.,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px( 500.0 ),
+            left: Val::Px( 550.0),
+            ..default()
+        }),
+        TitleScreen) );
+}
 
+fn title_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    touches: Res<Touches>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let pad = |button| pad_just_pressed( &gamepads, &pad_buttons, button );
+    if keyboard_input.any_just_pressed( [ KeyCode::Enter, KeyCode::Space ] )
+        || pad( GamepadButtonType::South ) || pad( GamepadButtonType::Start ) || touches.any_just_pressed() {
+        next_state.set( AppState::Setup );
+    }
+}
 
-fn handle_input(
-    camera_query: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
-    ground_query: Query<&GlobalTransform, With<Ground>>,
-    mut cursor_q: Query<(&mut Transform, &mut GameCursor)>,
-    maptile_query: Query<(Entity, &GlobalTransform, &MapSpaceVisual), With<MapSpaceVisual>>,
-    mouse_button_input: Res<ButtonInput<MouseButton>>,
-    windows: Query<&Window>,    
+fn spawn_setup(
+    mut commands: Commands,
     stuff: Res<GoodStuff>,
-    mut game: ResMut<GameState>,
-    mut ev_gamestate: EventWriter<GameStateChanged>,
-    mut ev_turn: EventWriter<TurnAdvance>,
-    mut gizmos: Gizmos,
+    asset_server: Res<AssetServer>,
+    mut ev_settings: EventWriter<PlayerSettingsChanged>,
 ) {
-    let (camera, camera_transform) = camera_query.single();
-    let ground = ground_query.single();
-
-    let Some(cursor_position) = windows.single().cursor_position() else {
-        return;
-    };
+    spawn_title_art( &mut commands, &asset_server, SetupScreen );
 
-    // Calculate a ray pointing from the camera into the world based on the cursor's position.
-    let Some(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
-        return;
-    };
+    let mut yy = 440.0;
+    for i in 0..4 {
+        
+            commands.spawn((
+                TextBundle::from_section("Player # -- ???",
+                    TextStyle {
+                        color: stuff.player_stuff[i].color,
+                        font_size: 30.,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(yy),
+                    left: Val::Px( 550.0),                
+                    min_width: Val::Px( 400.0 ),
+                    ..default()
+                }),                
+                Interaction::default(),
+                PlayerSetting(i as i32),
+                SetupScreen) );
+            yy += 30.0;        
+    }
+
+    commands.spawn((
+        TextBundle::from_section("Time -- ???",
+            TextStyle {
+                font_size: 30.,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(yy),
+            left: Val::Px( 550.0),
+            min_width: Val::Px( 400.0 ),
+            ..default()
+        }),
+        Interaction::default(),
+        TimeSetting,
+        SetupScreen) );
+    yy += 30.0;
+
+    // Big enough for a thumb
+    commands.spawn((
+        TextBundle::from_section("Start",
+            TextStyle {
+                font_size: 40.,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(yy + 60.0),
+            left: Val::Px( 550.0),
+            padding: UiRect::axes( Val::Px( 40.0 ), Val::Px( 12.0 ) ),
+            ..default()
+        })
+        .with_background_color( Color::rgba( 1.0, 1.0, 1.0, 0.15 ) ),
+        Interaction::default(),
+        StartButton,
+        SetupScreen) );
+
+    commands.spawn((
+        TextBundle::from_section("1-4 or tap: Change player    Shift+1-4: AI personality    Ctrl+1-4: 3-4 player search    T or tap: Time    Enter: Start    Esc: Back\nController: D-pad picks a player, A/X/RB change them, Y: Time, Start: Start, B: Back",
+            TextStyle {
+                font_size: 20.,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(yy + 10.0),
+            left: Val::Px( 550.0),
+            ..default()
+        }),
+        SetupScreen) );
+
+    ev_settings.send( PlayerSettingsChanged );
+}
+
+fn spawn_pause( mut commands: Commands )
+{
+    commands.spawn((
+        TextBundle::from_section("Paused    Esc/Start: Resume    M/Back: Menu",
+            TextStyle {
+                font_size: 42.,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px( 300.0 ),
+            left: Val::Px( 550.0),
+            ..default()
+        }),
+        PauseScreen) );
+}
+
+fn pause_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    cursor_q: Query<&GameCursor>,
+) {
+    let pad = |button| pad_just_pressed( &gamepads, &pad_buttons, button );
+    // Escape puts back a stack picked up with the keys instead
+    let holding = cursor_q.get_single().is_ok_and( |cursor| cursor.buttons && cursor.drag_from.is_some() );
+    if keyboard_input.just_pressed( KeyCode::Escape ) && holding && *state.get() == AppState::Playing {
+        return;
+    }
+    if keyboard_input.any_just_pressed( [ KeyCode::Escape, KeyCode::KeyP ] ) || pad( GamepadButtonType::Start ) {
+        next_state.set( match state.get() {
+            AppState::Paused => AppState::Playing,
+            _ => AppState::Paused,
+        });
+    } else if (keyboard_input.just_pressed( KeyCode::KeyM ) || pad( GamepadButtonType::Select )) && *state.get() == AppState::Paused {
+        next_state.set( AppState::Setup );
+    }
+}
+
+// R (or Start) deals another board for the same players, M (or Back) goes back to player setup
+fn game_over_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let pad = |button| pad_just_pressed( &gamepads, &pad_buttons, button );
+    if keyboard_input.just_pressed( KeyCode::KeyR ) || pad( GamepadButtonType::Start ) {
+        next_state.set( AppState::Playing );
+    } else if keyboard_input.just_pressed( KeyCode::KeyM ) || pad( GamepadButtonType::Select ) {
+        next_state.set( AppState::Setup );
+    }
+}
+
+// Clears the board and everything about the game away, leaving the player setup
+#[allow(clippy::too_many_arguments)]
+fn teardown_game(
+    mut commands: Commands,
+    mut stuff: ResMut<GoodStuff>,
+    mut game: ResMut<GameState>,
+    mut review: ResMut<Review>,
+    mut debug: ResMut<AIDebug>,
+    mut q_ai : Query<&mut AIController>,
+    mut hint_q: Query<&mut HintFinder>,
+    mapvis_q: Query<Entity, With<MapSpaceVisual>>,
+    mut camera_q: Query<&mut Transform, With<GameCamera>>,
+) {
+    // The rings go with the hexes they sit on
+    for e in &mapvis_q {
+        commands.entity(e).despawn_recursive();
+    }
+
+    for pinfo in &mut stuff.player_stuff {
+        pinfo.out_of_moves = false;
+    }
+    *game = GameState::default();
+    *review = Review::default();
+    debug.last_thought = None;
+
+    q_ai.single_mut().cancel();
+    let mut hint = hint_q.single_mut();
+    hint.search.cancel();
+    hint.for_turn = 0;
+    hint.suggestion = None;
+
+    // Wherever fingers left it
+    *camera_q.single_mut() = camera_home();
+}
+
+// The clock readouts, kept apart from the other texts reset_game_ui clears
+type ClockText = (With<PlayerClock>, Without<PlayerHelp>, Without<PlayerScore>);
+
+// The overlays that are hidden again for the next game
+type GameOverlay = Or<(With<DebugPanel>, With<DebugStackLabel>, With<GameLog>)>;
+
+fn reset_game_ui(
+    mut helper_q: Query<&mut Text, With<PlayerHelp>>,
+    mut score_q: Query<&mut Text, (With<PlayerScore>, Without<PlayerHelp>)>,
+    mut clock_q: Query<&mut Text, ClockText>,
+    mut turnicon_q: Query<&mut Sprite, With<TurnIcon>>,
+    mut overlay_q: Query<&mut Visibility, GameOverlay>,
+) {
+    let mut text = helper_q.single_mut();
+    text.sections[0].value = String::new();
+    for mut text in &mut score_q {
+        text.sections[0].value = "0".into();
+    }
+    for mut text in &mut clock_q {
+        text.sections[0].value = String::new();
+    }
+    for mut sprite in &mut turnicon_q {
+        sprite.color = Color::rgba( 1.0, 1.0, 1.0, 0.02 );
+    }
+    for mut vis in &mut overlay_q {
+        *vis = Visibility::Hidden;
+    }
+}
+
+fn despawn_screen<T : Component>( mut commands: Commands, screen_q: Query<Entity, With<T>> )
+{
+    for e in &screen_q {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+// Once there's a board on the table
+fn in_game( state: Res<State<AppState>> ) -> bool
+{
+    matches!( state.get(), AppState::Playing | AppState::RoundScoring | AppState::Paused | AppState::GameOver )
+}
+
+
+// fn test_start_game ( 
+//     // mut world : &mut World,
+//     mut commands: Commands,            
+//     keyboard_input: Res<ButtonInput<KeyCode>>,
+// )
+// {
+//     if keyboard_input.just_pressed( KeyCode::KeyW ) {
+//         println!("W pressed");
+//         //world.run_system_once(build_map);
+//     }
+// }
+
+// world.run_system_once(count_entities);
+// fn spawn_cards ( 
+//     mut commands: Commands,        
+//     cards: Res<CardDeck>,
+//     keyboard_input: Res<ButtonInput<KeyCode>>,
+// )
+// {
+//     if keyboard_input.just_pressed( KeyCode::KeyW ) {
+//         println!("W pressed");
+//         let mut rng = rand::thread_rng();
+//         commands.spawn((
+//             SpriteSheetBundle {
+//                 texture: cards.texture.clone(),
+//                 atlas: TextureAtlas {
+//                     layout: cards.layout.clone(),
+//                     index: rng.gen_range(1..20),
+//                 },                     
+//                 transform: Transform::from_xyz(rng.gen::<f32>() * 1000.0 - 500.0, rng.gen::<f32>() * 700.0 - 350.0, 0.0),
+//                 ..default()
+//             },        
+//         ));
+//     }
+// }
+
+
+
+#[allow(clippy::too_many_arguments)]
+fn handle_input(
+    camera_query: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    ground_query: Query<&GlobalTransform, With<Ground>>,
+    mut cursor_q: Query<(&mut Transform, &mut GameCursor)>,
+    maptile_query: Query<(Entity, &GlobalTransform, &MapSpaceVisual), With<MapSpaceVisual>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    touches: Res<Touches>,
+    windows: Query<&Window>,    
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+    mut ev_taken: EventWriter<TurnTaken>,
+    mut ev_mouse: EventReader<CursorMoved>,
+    mut ev_wheel: EventReader<MouseWheel>,
+    mut wheel: Local<f32>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut gizmos: Gizmos,
+) {
+    // The mouse takes over from the keys as soon as it's used
+    let mouse_used = ev_mouse.read().count() > 0 || mouse_button_input.get_just_pressed().next().is_some()
+        || touches.any_just_pressed();
+
+    // One finger works like the mouse, two are for the camera
+    let fingers = touches.iter().count();
+    let touch = touches.iter().next().filter( |_| fingers == 1 )
+        .or( touches.iter_just_released().next().filter( |_| fingers == 0 ) );
+    let held = fingers == 1 || mouse_button_input.pressed( MouseButton::Left );
+    let pressed = (fingers == 1 && touches.any_just_pressed()) || mouse_button_input.just_pressed( MouseButton::Left );
+    let released = (fingers == 0 && touches.any_just_released()) || mouse_button_input.just_released( MouseButton::Left );
+
+    // Wheel notches, or enough of a trackpad scroll to make one
+    for ev in ev_wheel.read() {
+        *wheel += match ev.unit {
+            MouseScrollUnit::Line => ev.y,
+            MouseScrollUnit::Pixel => ev.y / 40.0,
+        };
+    }
+    let mut nudge = wheel.trunc() as i32;
+    *wheel = wheel.fract();
+    if keyboard_input.any_just_pressed( [ KeyCode::Equal, KeyCode::NumpadAdd, KeyCode::ArrowUp ] ) {
+        nudge += 1;
+    }
+    if keyboard_input.any_just_pressed( [ KeyCode::Minus, KeyCode::NumpadSubtract, KeyCode::ArrowDown ] ) {
+        nudge -= 1;
+    }
+
+    {
+        let (_, mut cursor_info) = cursor_q.single_mut();
+        if mouse_used && cursor_info.buttons {
+            cursor_info.buttons = false;
+            cursor_info.drag_from = None;
+        }
+        if cursor_info.buttons {
+            return;
+        }
+        if fingers > 1 {
+            cursor_info.drag_from = None;
+            return;
+        }
+    }
+
+    let (camera, camera_transform) = camera_query.single();
+    let ground = ground_query.single();
+
+    let Some(cursor_position) = touch.map( |touch| touch.position() ).or( windows.single().cursor_position() ) else {
+        return;
+    };
+
+    // Calculate a ray pointing from the camera into the world based on the cursor's position.
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
+        return;
+    };
 
     // Calculate if and where the ray is hitting the ground plane.
     let Some(distance) = ray.intersect_plane(ground.translation(), Plane3d::new(ground.up()))
@@ -655,6 +1455,9 @@ fn handle_input(
         
         let active_player = game.player_turn;
 
+        // The computer players make their own moves, only a local player's turn can be dragged
+        let local_turn = !game.game_over && stuff.player_stuff[active_player as usize].ptype == PlayerType::Local;
+
         // Figure out split amount based on distance
         if cursor_info.drag_from.is_some() {
                 
@@ -665,74 +1468,36 @@ fn handle_input(
 
             //println!("Dist is {}, {}", d, dnorm );
             cursor_info.split_pct = dnorm;
+
+            let src_pow = game.snapshot.map.spaces[ drag_from_ndx as usize ].power as i32;
+            cursor_info.dir = mapdir_from_drag( cursor_info.cursor_world, drag_from_pos );
+            if nudge != 0 {
+                cursor_info.amount_set = true;
+                cursor_info.amount = (cursor_info.amount + nudge).clamp( 1, src_pow - 1 );
+            } else if !cursor_info.amount_set {
+                cursor_info.amount = calc_split( cursor_info.split_pct, src_pow );
+            }
         }
 
-        if mouse_button_input.just_pressed(MouseButton::Left) {
+        if pressed && local_turn {
 
-            // Make sure there is some power to drag from
-            if (ndx != INVALID) && (game.snapshot.map.spaces[ ndx ].power > 1 ) && 
-            (game.snapshot.map.spaces[ ndx ].player == (active_player + 1) as u8 ) {            
-                cursor_info.drag_from = Some( ndx );
-                println!("Drag from: {}", ndx );
+            // Make sure there is some power to drag from. A finger gets to grab
+            // the nearest stack it's close to, not just the one right under it.
+            let reach = if touch.is_some() { 1.5 * HEX_SZ } else { 0.0 };
+            if let Some(from) = grab_stack( &game, ndx, point, reach ) {
+                cursor_info.drag_from = Some( from );
+                cursor_info.amount_set = false;
+                println!("Drag from: {}", from );
             }
         }
         
-        if mouse_button_input.just_released(MouseButton::Left) {
-            
-            if cursor_info.drag_from.is_some() {
-                
-                let drag_from_ndx = cursor_info.drag_from.unwrap() as i32;
-                let drag_from_pos = worldpos_from_mapindex(drag_from_ndx as i32);        
-
-                let mapdir = mapdir_from_drag( cursor_info.cursor_world, drag_from_pos );
-                let found = game.snapshot.map.search_dir( drag_from_ndx,  mapdir );
-                if (found != drag_from_ndx) && (found != gamestate::INVALID as i32) 
-                {
-                    let found_ndx = found as usize;
-                    if game.snapshot.map.spaces[ found_ndx ].player == 0 {
-
-                        let src_pow = game.snapshot.map.spaces[ drag_from_ndx as usize ].power as i32;
-                        let split_count = calc_split(cursor_info.split_pct, src_pow);
-                        if split_count > 0 {
-                            game.snapshot.map.spaces[ found_ndx ].player = (active_player + 1) as u8;
-                            game.snapshot.map.spaces[ found_ndx ].power = split_count as u8;
-                            //ev_gamestate.send( GameStateChanged::CircleAdded( found_ndx as i32) );
-                            ev_gamestate.send( GameStateChanged::CircleSplit( drag_from_ndx, found_ndx as i32) );                            
-
-                            game.snapshot.map.spaces[ drag_from_ndx as usize].power -= split_count as u8;
-                            ev_gamestate.send( GameStateChanged::CircleAdded( drag_from_ndx) );
-
-
-                            // Advance to the next player's turn
-                            let mut pnum = game.player_turn;
-                            loop {
-                                pnum = pnum + 1;
-                                if pnum >= stuff.player_stuff.len() as i32 {
-                                    pnum = 0;
-                                }
-
-                                if stuff.player_stuff[pnum as usize].ptype != PlayerType::NotActive {
-                                    break;
-                                }
-
-                                if pnum == game.player_turn {
-                                    println!("Didn't find any active players?");
-                                    break;
-                                }
-                            }
-                            game.player_turn = pnum;
-                            game.turn_num += 1;
-
-                            game.snapshot.update_scores();
-
-                            ev_turn.send( TurnAdvance(pnum) );
-                        }
-                    }            
-                }
+        if let Some(drag_from) = cursor_info.drag_from.filter( |_| released && local_turn ) {
+            if let Some(mv) = cursor_move( &game, drag_from as i32, cursor_info.dir, cursor_info.amount ) {
+                ev_taken.send( TurnTaken { seat : active_player as usize, mv : Some( mv ) } );
             }
         }
 
-        if !mouse_button_input.pressed(MouseButton::Left) {
+        if !held {
             if cursor_info.drag_from.is_some() {
                 println!("Drag clear" );
             }
@@ -741,110 +1506,448 @@ fn handle_input(
     }
 }
 
-fn draw_map_dir( gizmos: &mut Gizmos, game : &GameState, ndx : i32, dir : MapDirection, color : Color, verbose : bool ) -> Vec3
-{    
-    let found = game.snapshot.map.search_dir( ndx,  dir );
-    if verbose {
-        let dir_str = format!("{:?}", dir);
-        let dir_str_padded = format!("{:<10}", dir_str);                    
-        println!("   {} {} Open {}", dir_str_padded, gamestate::move_dir( ndx, dir ),  found );    
+// What the keys or a controller asked the cursor to do this frame
+#[derive(Default)]
+struct CursorCommand {
+    step : Option<MapDirection>,
+    turn : i32,             // to the next (1) or previous (-1) open direction
+    amount_change : i32,
+    amount_set : Option<i32>,
+    confirm : bool,
+    cancel : bool,
+}
+
+fn key_command( keyboard_input : &ButtonInput<KeyCode> ) -> CursorCommand
+{
+    let step = [
+        (KeyCode::KeyW, MapDirection::North),
+        (KeyCode::KeyE, MapDirection::NorthEast),
+        (KeyCode::KeyD, MapDirection::SouthEast),
+        (KeyCode::KeyS, MapDirection::South),
+        (KeyCode::KeyA, MapDirection::SouthWest),
+        (KeyCode::KeyQ, MapDirection::NorthWest),
+    ].into_iter().find( |(key, _)| keyboard_input.just_pressed( *key ) ).map( |(_, dir)| dir );
+
+    let mut turn = 0;
+    if keyboard_input.just_pressed( KeyCode::Tab ) {
+        turn = if keyboard_input.any_pressed( [ KeyCode::ShiftLeft, KeyCode::ShiftRight ] ) { -1 } else { 1 };
     }
-    if (found != ndx) && (found != gamestate::INVALID as i32) {
-        let pos_a = worldpos_from_mapindex(ndx) + Vec3::Y * 0.25;
-        let pos_b = worldpos_from_mapindex(found) + Vec3::Y * 0.25;
-        gizmos.line(pos_a, pos_b, color );
-        gizmos.cuboid( 
-            Transform::from_translation(pos_b), //.with_scale(Vec3::splat(1.25)),
-            color );
-        
-        // Return the found pos
-        pos_b
 
-    } else {
-        Vec3::ZERO
+    let mut amount_change = 0;
+    if keyboard_input.any_just_pressed( [ KeyCode::Equal, KeyCode::NumpadAdd ] ) {
+        amount_change += 1;
+    }
+    if keyboard_input.any_just_pressed( [ KeyCode::Minus, KeyCode::NumpadSubtract ] ) {
+        amount_change -= 1;
     }
 
+    // 0 is ten
+    let digits = [
+        KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+        KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    ];
+    let amount_set = digits.iter().position( |key| keyboard_input.just_pressed( *key ) )
+        .map( |digit| if digit == 0 { 10 } else { digit as i32 } );
+
+    CursorCommand {
+        step,
+        turn,
+        amount_change,
+        amount_set,
+        confirm : keyboard_input.any_just_pressed( [ KeyCode::Enter, KeyCode::NumpadEnter, KeyCode::Space ] ),
+        cancel : keyboard_input.any_just_pressed( [ KeyCode::Backspace, KeyCode::Escape ] ),
+    }
+}
 
+// Any controller will do, they all drive whoever's turn it is
+fn pad_just_pressed( gamepads : &Gamepads, pad_buttons : &ButtonInput<GamepadButton>, button : GamepadButtonType ) -> bool
+{
+    gamepads.iter().any( |pad| pad_buttons.just_pressed( GamepadButton::new( pad, button ) ) )
 }
 
-fn mapdir_from_drag( pos : Vec3, start_pos : Vec3 ) -> MapDirection
+// The hex direction the left stick is pushed in, if it's pushed far enough
+fn stick_dir( gamepads : &Gamepads, axes : &Axis<GamepadAxis> ) -> Option<MapDirection>
 {
-    // get best angle from arrow
-    let dir = pos - start_pos;
-    let angle = dir.z.atan2(dir.x);
-    let mut angle_degrees = angle.to_degrees() + (90.0 + 30.0);
-    if angle_degrees < 0.0 {
-        angle_degrees = angle_degrees + 360.0;
-    }
-    
-    match (angle_degrees / 60.0).floor() as i32 {
-        0 => MapDirection::North,
-        1 => MapDirection::NorthEast,
-        2 => MapDirection::SouthEast,
-        3 => MapDirection::South,
-        4 => MapDirection::SouthWest,
-        5 => MapDirection::NorthWest,
-        _ => MapDirection::North, // Default case
+    for pad in gamepads.iter() {
+        let x = axes.get( GamepadAxis::new( pad, GamepadAxisType::LeftStickX ) ).unwrap_or( 0.0 );
+        let y = axes.get( GamepadAxis::new( pad, GamepadAxisType::LeftStickY ) ).unwrap_or( 0.0 );
+        if Vec2::new( x, y ).length() < 0.5 {
+            continue;
+        }
+        // Clockwise from straight up, in sixths
+        let degrees = (90.0 - y.atan2( x ).to_degrees() + 30.0).rem_euclid( 360.0 );
+        return MapDirection::iterator().nth( (degrees / 60.0) as usize % 6 );
     }
+    None
 }
 
-fn draw_split_feedback(
-    cursor_q: Query<(&Transform, &GameCursor)>,    
-    camera_q: Query<(&Camera, &Transform, &GlobalTransform), With<GameCamera>>,
-    mut label_q: Query<(&SplitLabel, &mut Style, &mut Text, &mut Visibility)>,    
-    stuff: Res<GoodStuff>,
-    game: Res<GameState>,
-    mut gizmos: Gizmos,
-)
+// Stick repeat, a step when it's pushed then more while it's held
+#[derive(Default)]
+struct StickRepeat {
+    dir : Option<MapDirection>,
+    wait : f32,
+}
+
+fn pad_command( gamepads : &Gamepads, pad_buttons : &ButtonInput<GamepadButton>, axes : &Axis<GamepadAxis>, repeat : &mut StickRepeat, dt : f32 ) -> CursorCommand
 {
-    let offs = Vec3 { x : 0.0, y : 0.15, z : 0.0 };
+    let pressed = |button| pad_just_pressed( gamepads, pad_buttons, button );
+
+    let dir = stick_dir( gamepads, axes );
+    let mut step = None;
+    if dir != repeat.dir {
+        step = dir;
+        repeat.wait = 0.35;
+    } else if dir.is_some() {
+        repeat.wait -= dt;
+        if repeat.wait <= 0.0 {
+            step = dir;
+            repeat.wait = 0.2;
+        }
+    }
+    repeat.dir = dir;
 
-    let ( _cursor_transform, cursor_info) = cursor_q.single();
-    let player_col = stuff.player_stuff[ game.player_turn as usize].color;
+    let mut turn = 0;
+    if pressed( GamepadButtonType::RightTrigger ) {
+        turn += 1;
+    }
+    if pressed( GamepadButtonType::LeftTrigger ) {
+        turn -= 1;
+    }
 
-    if cursor_info.drag_from.is_some() {
-        // Draw a gizmo for drag_from
-        let drag_from_ndx = cursor_info.drag_from.unwrap();
-        let drag_from_pos = worldpos_from_mapindex(drag_from_ndx as i32);        
-        gizmos.arrow( drag_from_pos + offs, cursor_info.cursor_world + offs, Color::YELLOW );
+    let mut amount_change = 0;
+    if pressed( GamepadButtonType::RightTrigger2 ) {
+        amount_change += 1;
+    }
+    if pressed( GamepadButtonType::LeftTrigger2 ) {
+        amount_change -= 1;
+    }
 
-        // cursor_info.cursor_world - drag_from_pos;
-        let mapdir = mapdir_from_drag( cursor_info.cursor_world, drag_from_pos );        
-        let dst_pos = draw_map_dir( &mut gizmos, &game, drag_from_ndx as i32, mapdir, player_col, false);
+    CursorCommand {
+        step,
+        turn,
+        amount_change,
+        amount_set : None,
+        confirm : pressed( GamepadButtonType::South ),
+        cancel : pressed( GamepadButtonType::East ),
+    }
+}
 
-        let src_pow = game.snapshot.map.spaces[ drag_from_ndx ].power as i32;
-        let split_count = calc_split(cursor_info.split_pct, src_pow);
+// The stack of the player to move that a press at `point` picks up, the one on
+// the hex under it or failing that the nearest one within reach
+fn grab_stack( game : &GameState, ndx : usize, point : Vec3, reach : f32 ) -> Option<usize>
+{
+    let owner = (game.player_turn + 1) as u8;
+    let can_split = |space : &gamestate::MapSpace| space.power > 1 && space.player == owner;
+    if ndx != INVALID && can_split( &game.snapshot.map.spaces[ ndx ] ) {
+        return Some( ndx );
+    }
 
-        for (lblinfo, mut style, mut label, mut vis) in &mut label_q {
-            
-            let wpos;                        
-            if lblinfo.is_dest {
-                label.sections[0].value = format!("{}", split_count );
-                wpos = dst_pos;
-            } else {            
-                label.sections[0].value = format!("{}", src_pow - split_count );
-                wpos = drag_from_pos;
-            }
-            label.sections[0].style.color = player_col;
-            
-            let (camera, _camera_transform, camera_global_transform) = camera_q.single();
-            let viewport_position = camera
-                .world_to_viewport(camera_global_transform, wpos)
-                .unwrap();
+    game.snapshot.map.spaces.iter()
+        .filter( |space| can_split( space ) )
+        .map( |space| (space.ndx as usize, worldpos_from_mapindex( space.ndx ).distance( point )) )
+        .filter( |(_, dist)| *dist <= reach )
+        .min_by( |a, b| a.1.total_cmp( &b.1 ) )
+        .map( |(ndx, _)| ndx )
+}
 
-            style.top = Val::Px(viewport_position.y);
-            style.left = Val::Px(viewport_position.x);
+// Where the camera starts, and goes back to for each game
+fn camera_home() -> Transform {
+    Transform::from_xyz( 0.0, 15.0, 12.0).looking_at( Vec3 { x:0.0, y: 0.0, z : 3.0 }, Vec3::Y)
+}
 
-            *vis = Visibility::Visible;
+// Two fingers move the camera, pinching to zoom and dragging to pan. The spot
+// on the board between them stays between them.
+fn touch_camera(
+    touches: Res<Touches>,
+    mut camera_q: Query<(&Camera, &mut Transform, &GlobalTransform), With<GameCamera>>,
+    ground_q: Query<&GlobalTransform, With<Ground>>,
+) {
+    let fingers : Vec<_> = touches.iter().collect();
+    let [a, b] = fingers[..] else {
+        return;
+    };
+    let (camera, mut transform, camera_global) = camera_q.single_mut();
+    let ground = ground_q.single();
+    let on_ground = |screen : Vec2| {
+        let ray = camera.viewport_to_world( camera_global, screen )?;
+        let distance = ray.intersect_plane( ground.translation(), Plane3d::new( ground.up() ) )?;
+        Some( ray.get_point( distance ) )
+    };
 
+    let (Some(was), Some(now)) = (
+        on_ground( (a.previous_position() + b.previous_position()) / 2.0 ),
+        on_ground( (a.position() + b.position()) / 2.0 ),
+    ) else {
+        return;
+    };
+    transform.translation += was - now;
+
+    // After the pan the spot that was between the fingers is again, zoom on that
+    let span_was = a.previous_position().distance( b.previous_position() );
+    let span = a.position().distance( b.position() );
+    if span_was > 1.0 && span > 1.0 {
+        let zoomed = was + (transform.translation - was) * (span_was / span);
+        if (5.0..=30.0).contains( &zoomed.y ) {
+            transform.translation = zoomed;
         }
+    }
+}
 
-        // println!( "Drag angle: {} degrees dir {:?}", angle_degrees, mapdir );
-    } else {
-        // not dragging, should we show preview?                
-        let ndx = cursor_info.ndx as i32;
-
-        // Hide the split labels
+// Playing with the keys or a controller.
+//
+// Keys: QWEASD step the cursor over the hexes, Enter or Space picks up the stack
+// under it, then QWEASD or Tab point the split, +/- or the number keys set how
+// much goes, Enter plays it and Backspace or Escape puts it back.
+//
+// Controller: the left stick steps the cursor, A picks up and plays, B puts it
+// back, the stick or shoulder buttons point the split and the triggers set how much.
+#[allow(clippy::too_many_arguments)]
+fn button_play(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    time: Res<Time>,
+    mut repeat: Local<StickRepeat>,
+    mut cursor_q: Query<(&mut Transform, &mut GameCursor)>,
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+    mut ev_taken: EventWriter<TurnTaken>,
+) {
+    let (mut cursor_transform, mut cursor_info) = cursor_q.single_mut();
+    let seat = game.player_turn as usize;
+    if game.game_over || stuff.player_stuff[seat].ptype != PlayerType::Local {
+        if cursor_info.buttons {
+            cursor_info.drag_from = None;
+        }
+        return;
+    }
+
+    let keys = key_command( &keyboard_input );
+    let pad = pad_command( &gamepads, &pad_buttons, &axes, &mut repeat, time.delta_seconds() );
+    let command = CursorCommand {
+        step : keys.step.or( pad.step ),
+        turn : keys.turn + pad.turn,
+        amount_change : keys.amount_change + pad.amount_change,
+        amount_set : keys.amount_set,
+        confirm : keys.confirm || pad.confirm,
+        cancel : keys.cancel || pad.cancel,
+    };
+
+    if !cursor_info.buttons {
+        if command.step.is_none() && !command.confirm {
+            return;
+        }
+        // Taking over from the mouse, drop whatever it was dragging
+        cursor_info.buttons = true;
+        cursor_info.drag_from = None;
+    }
+
+    let owner = (seat + 1) as u8;
+    let open = |from : i32, dir : MapDirection| {
+        let found = game.snapshot.map.search_dir( from, dir );
+        found != from && found != gamestate::INVALID as i32
+    };
+
+    if let Some(from) = cursor_info.drag_from {
+        let from = from as i32;
+        let src_pow = game.snapshot.map.spaces[ from as usize ].power as i32;
+
+        if command.cancel {
+            cursor_info.drag_from = None;
+            return;
+        }
+
+        if let Some(dir) = command.step {
+            cursor_info.dir = dir;
+        }
+        if command.turn != 0 {
+            // Round to the next way there is room to split
+            let mut dir = cursor_info.dir;
+            for _ in 0..6 {
+                dir = if command.turn < 0 { dir.prev() } else { dir.next() };
+                if open( from, dir ) {
+                    break;
+                }
+            }
+            cursor_info.dir = dir;
+        }
+
+        cursor_info.amount += command.amount_change;
+        if let Some(amount) = command.amount_set {
+            cursor_info.amount = amount;
+        }
+        cursor_info.amount = cursor_info.amount.clamp( 1, (src_pow - 1).max( 1 ) );
+
+        if command.confirm {
+            if let Some(mv) = cursor_move( &game, from, cursor_info.dir, cursor_info.amount ) {
+                ev_taken.send( TurnTaken { seat, mv : Some( mv ) } );
+                cursor_info.drag_from = None;
+            }
+        }
+    } else {
+        let mut ndx = cursor_info.ndx as i32;
+        let on_map = (0..100).contains( &ndx ) && game.snapshot.map.spaces[ ndx as usize ].contents == MapSpaceContents::Playable;
+        if !on_map {
+            // Start off on one of our own stacks
+            if let Some(space) = game.snapshot.map.spaces.iter().find( |space| space.player == owner && space.power > 0 ) {
+                ndx = space.ndx;
+            }
+        } else if let Some(dir) = command.step {
+            // Over any holes in the map to the next hex that's part of it
+            let mut next = gamestate::move_dir( ndx, dir );
+            while next != gamestate::INVALID as i32 && game.snapshot.map.spaces[ next as usize ].contents != MapSpaceContents::Playable {
+                next = gamestate::move_dir( next, dir );
+            }
+            if next != gamestate::INVALID as i32 {
+                ndx = next;
+            }
+        }
+        cursor_info.ndx = ndx as usize;
+
+        let space = game.snapshot.map.spaces[ cursor_info.ndx ];
+        if command.confirm && on_map && space.player == owner && space.power > 1 {
+            cursor_info.drag_from = Some( cursor_info.ndx );
+            cursor_info.dir = MapDirection::iterator().find( |dir| open( ndx, *dir ) ).unwrap_or( MapDirection::North );
+            cursor_info.amount = space.power as i32 / 2;
+        }
+    }
+
+    cursor_info.cursor_world = worldpos_from_mapindex( cursor_info.ndx as i32 );
+    cursor_transform.translation = cursor_info.cursor_world;
+}
+
+// The split the cursor is set up for, if it's one that can be played
+fn cursor_move( game : &GameState, from : i32, dir : MapDirection, amount : i32 ) -> Option<SplitMove>
+{
+    let found = game.snapshot.map.search_dir( from, dir );
+    if found == from || found == gamestate::INVALID as i32 || game.snapshot.map.spaces[ found as usize ].player != 0 {
+        return None;
+    }
+    let src_pow = game.snapshot.map.spaces[ from as usize ].power as i32;
+    if amount < 1 || amount >= src_pow {
+        return None;
+    }
+    Some( SplitMove { from, to : found, amount : amount as u8 } )
+}
+
+fn draw_map_dir( gizmos: &mut Gizmos, game : &GameState, ndx : i32, dir : MapDirection, color : Color, verbose : bool ) -> Vec3
+{    
+    let found = game.snapshot.map.search_dir( ndx,  dir );
+    if verbose {
+        let dir_str = format!("{:?}", dir);
+        let dir_str_padded = format!("{:<10}", dir_str);                    
+        println!("   {} {} Open {}", dir_str_padded, gamestate::move_dir( ndx, dir ),  found );    
+    }
+    if (found != ndx) && (found != gamestate::INVALID as i32) {
+        let pos_a = worldpos_from_mapindex(ndx) + Vec3::Y * 0.25;
+        let pos_b = worldpos_from_mapindex(found) + Vec3::Y * 0.25;
+        gizmos.line(pos_a, pos_b, color );
+        gizmos.cuboid( 
+            Transform::from_translation(pos_b), //.with_scale(Vec3::splat(1.25)),
+            color );
+        
+        // Return the found pos
+        pos_b
+
+    } else {
+        Vec3::ZERO
+    }
+
+
+}
+
+fn mapdir_from_drag( pos : Vec3, start_pos : Vec3 ) -> MapDirection
+{
+    // get best angle from arrow
+    let dir = pos - start_pos;
+    let angle = dir.z.atan2(dir.x);
+    let mut angle_degrees = angle.to_degrees() + (90.0 + 30.0);
+    if angle_degrees < 0.0 {
+        angle_degrees = angle_degrees + 360.0;
+    }
+    
+    match (angle_degrees / 60.0).floor() as i32 {
+        0 => MapDirection::North,
+        1 => MapDirection::NorthEast,
+        2 => MapDirection::SouthEast,
+        3 => MapDirection::South,
+        4 => MapDirection::SouthWest,
+        5 => MapDirection::NorthWest,
+        _ => MapDirection::North, // Default case
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_split_feedback(
+    cursor_q: Query<(&Transform, &GameCursor)>,    
+    camera_q: Query<(&Camera, &Transform, &GlobalTransform), With<GameCamera>>,
+    mut label_q: Query<(&SplitLabel, &mut Style, &mut Text, &mut Visibility)>,    
+    hint_q: Query<&HintFinder>,
+    review: Res<Review>,
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+    mut gizmos: Gizmos,
+)
+{
+    let offs = Vec3 { x : 0.0, y : 0.15, z : 0.0 };
+
+    let ( _cursor_transform, cursor_info) = cursor_q.single();
+    let player_col = stuff.player_stuff[ game.player_turn as usize].color;
+    let (camera, _camera_transform, camera_global_transform) = camera_q.single();
+
+    if cursor_info.drag_from.is_some() {
+        // Draw a gizmo for drag_from
+        let drag_from_ndx = cursor_info.drag_from.unwrap();
+        let drag_from_pos = worldpos_from_mapindex(drag_from_ndx as i32);        
+
+        let mapdir = cursor_info.dir;
+        let dst_pos = draw_map_dir( &mut gizmos, &game, drag_from_ndx as i32, mapdir, player_col, false);
+        if cursor_info.buttons {
+            // The other ways it could go, dimmed
+            for dir in MapDirection::iterator().filter( |dir| *dir != mapdir ) {
+                draw_map_dir( &mut gizmos, &game, drag_from_ndx as i32, dir, player_col.with_a( 0.3 ), false );
+            }
+            if dst_pos != Vec3::ZERO {
+                gizmos.arrow( drag_from_pos + offs, dst_pos + offs, Color::YELLOW );
+            }
+        }
+
+        let src_pow = game.snapshot.map.spaces[ drag_from_ndx ].power as i32;
+        let split_count = cursor_info.amount;
+
+        if !cursor_info.buttons {
+            // The arrow snaps to the detent for the amount, with a tick for each of the others
+            let along = ((cursor_info.cursor_world - drag_from_pos) * Vec3::new( 1.0, 0.0, 1.0 )).normalize_or_zero();
+            for amount in 1..src_pow {
+                let tick = drag_from_pos + along * split_drag_dist( amount, src_pow ) + offs;
+                let (radius, color) = if amount == split_count { (0.12, Color::YELLOW) } else { (0.05, player_col) };
+                gizmos.circle( tick, Direction3d::Y, radius, color );
+            }
+            let snapped = drag_from_pos + along * split_drag_dist( split_count, src_pow );
+            gizmos.arrow( drag_from_pos + offs, snapped + offs, Color::YELLOW );
+        }
+
+        show_split_labels( &mut label_q, camera, camera_global_transform, drag_from_pos, dst_pos, src_pow, split_count, player_col );
+
+        // println!( "Drag angle: {} degrees dir {:?}", angle_degrees, mapdir );
+    } else if let Some(mv) = hint_q.single().suggestion.or( review.viewing.and_then( |ply| review.moves[ply].better_move() ) ) {
+        // Show the hint, or the better move when reviewing, the same way as a drag
+        // with the amounts filled in
+        let Some(mapdir) = MapDirection::iterator().find( |dir| game.snapshot.map.search_dir( mv.from, *dir ) == mv.to ) else {
+            return;
+        };
+        let src_pos = worldpos_from_mapindex( mv.from );
+        let dst_pos = draw_map_dir( &mut gizmos, &game, mv.from, mapdir, Color::WHITE, false );
+        gizmos.arrow( src_pos + offs, dst_pos + offs, Color::WHITE );
+
+        let src_pow = game.snapshot.map.spaces[ mv.from as usize ].power as i32;
+        show_split_labels( &mut label_q, camera, camera_global_transform, src_pos, dst_pos, src_pow, mv.amount as i32, player_col );
+    } else {
+        // not dragging, should we show preview?                
+        let ndx = cursor_info.ndx as i32;
+
+        // Hide the split labels
         for (_, _, _, mut vis) in &mut label_q {
             *vis = Visibility::Hidden;
         }
@@ -852,6 +1955,10 @@ fn draw_split_feedback(
         // look at the hovered square
         if (ndx >= 0) && (ndx < 100) {
             let mapsq = game.snapshot.map.spaces[ ndx as usize ];
+
+            if cursor_info.buttons {
+                gizmos.circle( worldpos_from_mapindex( ndx ) + offs, Direction3d::Y, HEX_SZ * 0.45, Color::WHITE );
+            }
             
             // TODO: player check
             if (mapsq.contents == MapSpaceContents::Playable) && (mapsq.power > 1) && (mapsq.player == (game.player_turn + 1) as u8) {                
@@ -868,26 +1975,92 @@ fn draw_split_feedback(
             
 }
 
+// See-through rings for the stacks a split would leave, over the ones there now
+fn update_split_ghosts(
+    cursor_q: Query<&GameCursor>,
+    mut ghost_q: Query<(&SplitGhost, &mut Transform, &mut Handle<StandardMaterial>, &mut Visibility)>,
+    state: Res<State<AppState>>,
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+)
+{
+    let Ok(cursor_info) = cursor_q.get_single() else {
+        return;
+    };
+    let mv = match cursor_info.drag_from {
+        Some(from) if *state.get() == AppState::Playing => cursor_move( &game, from as i32, cursor_info.dir, cursor_info.amount ),
+        _ => None,
+    };
+
+    for (ghost, mut transform, mut mtl, mut vis) in &mut ghost_q {
+        let Some(mv) = mv else {
+            *vis = Visibility::Hidden;
+            continue;
+        };
+        let src = game.snapshot.map.spaces[ mv.from as usize ];
+        let (ndx, power) = if ghost.is_dest {
+            (mv.to, mv.amount as usize)
+        } else {
+            (mv.from, src.power as usize - mv.amount as usize)
+        };
+
+        let ring_sz = if power == 1 { 0.9 } else { 1.25 };
+        *transform = Transform::from_translation( worldpos_from_mapindex( ndx ) + Vec3::Y * 0.35 ).with_scale( Vec3::splat( ring_sz ) );
+        *mtl = stuff.player_stuff[ src.player as usize - 1 ].ghost_mtl[ power - 1 ].clone();
+        *vis = Visibility::Visible;
+    }
+}
+
+// Put the split amounts over the source and destination stacks
+#[allow(clippy::too_many_arguments)]
+fn show_split_labels(
+    label_q: &mut Query<(&SplitLabel, &mut Style, &mut Text, &mut Visibility)>,
+    camera: &Camera,
+    camera_global_transform: &GlobalTransform,
+    src_pos : Vec3,
+    dst_pos : Vec3,
+    src_pow : i32,
+    split_count : i32,
+    color : Color,
+)
+{
+    for (lblinfo, mut style, mut label, mut vis) in label_q.iter_mut() {
+        let (count, wpos) = if lblinfo.is_dest {
+            (split_count, dst_pos)
+        } else {
+            (src_pow - split_count, src_pos)
+        };
+        label.sections[0].value = format!("{}", count );
+        label.sections[0].style.color = color;
+
+        let Some(viewport_position) = camera.world_to_viewport(camera_global_transform, wpos) else {
+            continue;
+        };
+        style.top = Val::Px(viewport_position.y);
+        style.left = Val::Px(viewport_position.x);
+
+        *vis = Visibility::Visible;
+    }
+}
+
+// The drag is cut into even detents, one for each amount that can be split off
 fn calc_split( split_pct : f32, src_pow: i32) -> i32 {
-    let split_count = split_pct * ((src_pow - 1) as f32);
-    let split_count = split_count as i32;
-    split_count
+    let detents = (src_pow - 1).max( 1 );
+    let split_count = (split_pct * detents as f32) as i32 + 1;
+    split_count.min( detents )
+}
+
+// How far to drag for an amount, the middle of its detent
+fn split_drag_dist( amount : i32, src_pow : i32 ) -> f32 {
+    let detents = (src_pow - 1).max( 1 );
+    1.0 + 3.0 * ((amount as f32 - 0.5) / detents as f32)
 }
 
 
 fn worldpos_from_mapindex( mapindex : i32 ) -> Vec3
 {
-    let row : i32 = mapindex / (gamestate::MAP_SZ as i32);
-    let col : i32 = mapindex % (gamestate::MAP_SZ as i32);
-
-    // offset if col is odd
-    
-
-    // Make a vec3 from row and col        
-    let sqrt3 = 1.7320508075688772;
-    let offset = if col % 2 == 1 { HEX_SZ * sqrt3 / 2.0 } else { 0.0 };
-    Vec3::new((col as f32 - 4.5) * (HEX_SZ * (3.0/2.0) ), 0.0,
-    (-row as f32 + 5.0) * (HEX_SZ * sqrt3) + offset )
+    let (x, z) = gamestate::hex_position( mapindex );
+    Vec3::new( x * HEX_SZ, 0.0, z * HEX_SZ )
 }
 
 // fn spawn_mapspace_empty( mut commands: Commands ) -> Entity {
@@ -899,77 +2072,134 @@ fn worldpos_from_mapindex( mapindex : i32 ) -> Vec3
 //     }).id()
 // }
 
-fn build_map (
-    asset_server: Res<AssetServer>,
+// Player setup, 1-4 cycle through what plays each seat. On a controller the
+// d-pad picks a seat, A and X cycle it like 1-4 and Shift+1-4, Y is the time
+#[allow(clippy::too_many_arguments)]
+fn setup_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut pad_seat: ResMut<PadSeat>,
+    tapped_q: Query<SetupTap, Changed<Interaction>>,
     mut stuff: ResMut<GoodStuff>,
-    mut commands: Commands,
-    mut gamestate: ResMut<GameState>,
-    mut meshes: ResMut<Assets<Mesh>>,    
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut ev_gamestate: EventWriter<GameStateChanged>,
-    mut ev_turn: EventWriter<TurnAdvance>,
+    engine_seats: Res<EngineSeats>,
     mut ev_settings: EventWriter<PlayerSettingsChanged>,
-    titlescreen_q : Query<Entity, With<TitleScreenCrap>>,    
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-) 
-{   
+    mut clocks: ResMut<MatchClocks>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let pad = |button| pad_just_pressed( &gamepads, &pad_buttons, button );
 
-    // already built
-    if (gamestate.player_count > 0) {
+    if keyboard_input.just_pressed( KeyCode::Escape ) || pad( GamepadButtonType::East ) {
+        next_state.set( AppState::Title );
         return;
     }
 
-    // this all sucks but the contest is ending
-    if (gamestate.player_count == 0) {
+    let mut seat_change = 0;
+    if pad( GamepadButtonType::DPadDown ) {
+        seat_change = 1;
+    }
+    if pad( GamepadButtonType::DPadUp ) {
+        seat_change = 3;
+    }
+    if seat_change != 0 {
+        pad_seat.0 = Some( pad_seat.0.map_or( 0, |seat| (seat + seat_change) % 4 ) );
+        ev_settings.send( PlayerSettingsChanged );
+    }
 
-        let mut should_run = keyboard_input.just_pressed( KeyCode::Enter ) || keyboard_input.just_pressed( KeyCode::Space );
+    // A tap or click on one of the lines works like its key
+    let mut tapped_seat = None;
+    let mut tapped_time = false;
+    let mut tapped_start = false;
+    for (interaction, seat, time, start) in &tapped_q {
+        if *interaction == Interaction::Pressed {
+            tapped_seat = seat.map( |seat| seat.0 ).or( tapped_seat );
+            tapped_time |= time;
+            tapped_start |= start;
+        }
+    }
 
+    if keyboard_input.just_pressed( KeyCode::KeyT ) || pad( GamepadButtonType::North ) || tapped_time {
+        clocks.0 = Clocks::new( clocks.0.control.next() );
+        ev_settings.send( PlayerSettingsChanged );
+    }
 
-        let mut z = -1;
-        if keyboard_input.just_pressed( KeyCode::Digit1) {
-            z = 0;
-        }
-        
-        if keyboard_input.just_pressed( KeyCode::Digit2) {
-            z = 1;
-        }
+    let mut z = -1;
+    if keyboard_input.just_pressed( KeyCode::Digit1) {
+        z = 0;
+    }
+    
+    if keyboard_input.just_pressed( KeyCode::Digit2) {
+        z = 1;
+    }
 
-        if keyboard_input.just_pressed( KeyCode::Digit3) {
-            z = 2;
-        }
+    if keyboard_input.just_pressed( KeyCode::Digit3) {
+        z = 2;
+    }
 
-        if keyboard_input.just_pressed( KeyCode::Digit4) {
-            z = 3;
-        }
+    if keyboard_input.just_pressed( KeyCode::Digit4) {
+        z = 3;
+    }
 
-        if z >= 0 {
-            let z = z as usize;
-            if stuff.player_stuff[z].ptype == PlayerType::Local {
-                stuff.player_stuff[z].ptype = PlayerType::AI;
-            } else if stuff.player_stuff[z].ptype == PlayerType::AI {
-                stuff.player_stuff[z].ptype = PlayerType::NotActive;
-            } else if stuff.player_stuff[z].ptype == PlayerType::NotActive {
-                stuff.player_stuff[z].ptype = PlayerType::Local;
-            }
+    let mut shift = keyboard_input.any_pressed( [ KeyCode::ShiftLeft, KeyCode::ShiftRight ] );
+    let mut ctrl = keyboard_input.any_pressed( [ KeyCode::ControlLeft, KeyCode::ControlRight ] );
+    if pad( GamepadButtonType::South ) || pad( GamepadButtonType::West ) || pad( GamepadButtonType::RightTrigger ) {
+        let seat = pad_seat.0.unwrap_or( 0 );
+        pad_seat.0 = Some( seat );
+        z = seat as i32;
+        shift = pad( GamepadButtonType::West );
+        ctrl = pad( GamepadButtonType::RightTrigger );
+    }
 
-            ev_settings.send( PlayerSettingsChanged );
-        }
+    if let Some(seat) = tapped_seat {
+        z = seat;
+    }
 
-        let mut pcount : i32 = 0;
-        for i in 0..4 {
-            if stuff.player_stuff[i].ptype != PlayerType::NotActive {
-                pcount += 1;
-            }
-        }
+    if z >= 0 {
+        let z = z as usize;
+        stuff.player_stuff[z].ptype = match stuff.player_stuff[z].ptype {
+            PlayerType::AI(policy) if ctrl => PlayerType::AI( AIPolicy { multi : policy.multi.next(), ..policy } ),
+            PlayerType::AI(policy) if shift => PlayerType::AI( AIPolicy { personality : policy.personality.next(), ..policy } ),
+            ptype if shift || ctrl => ptype,
+            PlayerType::Local => PlayerType::AI( AIPolicy { difficulty : Difficulty::Easy, ..default() } ),
+            PlayerType::AI(policy) => match policy.difficulty {
+                Difficulty::Easy => PlayerType::AI( AIPolicy { difficulty : Difficulty::Medium, ..policy } ),
+                Difficulty::Medium => PlayerType::AI( AIPolicy { difficulty : Difficulty::Hard, ..policy } ),
+                Difficulty::Hard => PlayerType::AI( AIPolicy { difficulty : Difficulty::HardMcts, ..policy } ),
+                Difficulty::HardMcts if !engine_seats.commands.is_empty() => PlayerType::External( 0 ),
+                Difficulty::HardMcts => PlayerType::NotActive,
+            },
+            PlayerType::External(index) if (index as usize) + 1 < engine_seats.commands.len() => PlayerType::External( index + 1 ),
+            PlayerType::External(_) => PlayerType::NotActive,
+            PlayerType::NotActive => PlayerType::Local,
+        };
 
-        if !should_run || pcount == 0 { return };
+        ev_settings.send( PlayerSettingsChanged );
     }
 
-    // Despawn all the title screen stuff
-    for e in &titlescreen_q {
-        commands.entity(e).despawn_recursive();
+    let start = keyboard_input.any_just_pressed( [ KeyCode::Enter, KeyCode::Space ] ) || pad( GamepadButtonType::Start ) || tapped_start;
+    if start && active_seats( &stuff ) != 0 {
+        next_state.set( AppState::Playing );
     }
+}
 
+// Deals a new board when the game starts
+#[allow(clippy::too_many_arguments)]
+fn build_map (
+    asset_server: Res<AssetServer>,
+    mut stuff: ResMut<GoodStuff>,
+    mut commands: Commands,
+    mut gamestate: ResMut<GameState>,
+    mut meshes: ResMut<Assets<Mesh>>,    
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut ev_gamestate: EventWriter<GameStateChanged>,
+    mut ev_turn: EventWriter<TurnAdvance>,
+    mut ev_over: EventWriter<GameOver>,
+    mut ev_settings: EventWriter<PlayerSettingsChanged>,
+    mut stats: ResMut<MatchStats>,
+    mut engine_seats: ResMut<EngineSeats>,
+    mut clocks: ResMut<MatchClocks>,
+) 
+{   
     // Count number of active players to get target size for map
     let mut player_count = 0;
     for i in 0..stuff.player_stuff.len() {
@@ -980,74 +2210,31 @@ fn build_map (
 
     // Remember this
     gamestate.player_count = player_count;
+    *stats = MatchStats::default();
 
 
-    // First, set up the map indices and build the map
     let mut rng = rand::thread_rng();
-    let mut index = 0;
-    let mut space_count = 0;    
-    for map_space in &mut gamestate.snapshot.map {
-        map_space.ndx = index;
-        index = index + 1;
-
-        let hex_pos = worldpos_from_mapindex( map_space.ndx );
-
-        // this trims the board and makes it more rounder
-        if hex_pos.length() < 8.0 {
-            
-            // todo: replace this with adding some obstacles with preset shapes
-            if rng.gen_ratio(1, 8) {
-                map_space.contents = MapSpaceContents::Blocked;
-            } else {
-                map_space.contents = MapSpaceContents::Playable;
-                space_count += 1;                
-            }            
-        }
+    gamestate.snapshot = gamestate::generate_map( active_seats( &stuff ), &mut rng );
+    gamestate.start = gamestate.snapshot;
+    gamestate.history.clear();
+    gamestate.log.clear();
+    gamestate.bonus_points = [0; 4];
+    gamestate.game_over = false;
+    clocks.0 = Clocks::new( clocks.0.control );
+    gamestate.player_turn = Position::new( gamestate.start, 0, active_seats( &stuff ) ).next_seat( 3 ) as i32;
+
+    let start = Position::new( gamestate.start, gamestate.player_turn as usize, active_seats( &stuff ) );
+    if engine_seats.start_game( &mut stuff, &start ) {
+        ev_settings.send( PlayerSettingsChanged );
     }
-        
 
+    let space_count = gamestate.snapshot.map.spaces.iter().filter( |s| s.contents == MapSpaceContents::Playable ).count();
     println!("Hello from build_map, Players {} target spaces {} have {}.", 
             player_count, player_count * 16, space_count );
 
-    let target_spaces = player_count * 16;
-    let mut attempts = 1000;
-    while space_count > target_spaces && attempts > 0{
-        // erode away the board edges
-        let edge_spaces = gamestate.snapshot.map.edge_spaces_corners();
-                
-        let random_index = rng.gen_range(0..edge_spaces.len());
-        let selected_index = edge_spaces[random_index];        
-        
-        // Try removing this space
-        let mut map_copy = gamestate.snapshot.map;
-        map_copy.spaces[selected_index as usize].contents = MapSpaceContents::NotInMap;
-
-        if map_copy.check_reachability() {    
-            //gamestate.snapshot.map.spaces[selected_index].contents = MapSpaceContents::NotInMap;
-            gamestate.snapshot.map = map_copy;
-            space_count -= 1;
-        }
-
-        attempts -= 1;
-        println!("iter {} edge spaces now has {} entries space_count {}", attempts, edge_spaces.len(), space_count );
-    }
-
-    if attempts == 0 {
-        println!("Warning! Failed to erode map.");
-    }
-
-    // Find starting spaces
-    let mut edge_spaces = gamestate.snapshot.map.edge_spaces();
-    edge_spaces.shuffle( &mut rng );
-
-    for i in 0..stuff.player_stuff.len() {
-        if stuff.player_stuff[i].ptype != PlayerType::NotActive {            
-            let selected_index = edge_spaces[i] as usize;
-
-            gamestate.snapshot.map.spaces[ selected_index ].player = (i+1) as u8;
-            gamestate.snapshot.map.spaces[ selected_index ].power = 16;
-
-            ev_gamestate.send( GameStateChanged::CircleAdded( selected_index as i32 ) );
+    for map_space in &gamestate.snapshot.map {
+        if map_space.power > 0 {
+            ev_gamestate.send( GameStateChanged::CircleAdded( map_space.ndx ) );
         }
     }
 
@@ -1086,27 +2273,40 @@ fn build_map (
 
     println!("Map size {}", gamestate.map_visuals.len());    
 
-    // Send a turn advance to update the player prompt
-    ev_turn.send( TurnAdvance(gamestate.player_turn) );
+    // Hand the first turn out, which also updates the player prompt
+    begin_turn( &mut stuff, &mut gamestate, &mut ev_turn, &mut ev_over );
 
 }
 
 fn player_settings(     
     mut stuff: ResMut<GoodStuff>,
+    engine_seats: Res<EngineSeats>,
+    clocks: Res<MatchClocks>,
+    pad_seat: Res<PadSeat>,
     mut setting_q: Query<(&mut Text, &PlayerSetting)>,
+    mut time_q: Query<&mut Text, (With<TimeSetting>, Without<PlayerSetting>)>,
     mut ev_settings: EventReader<PlayerSettingsChanged>,
 ) {
     for ev in ev_settings.read() {
 
         for (mut text, plr) in &mut setting_q {
             
-            let plrType = match stuff.player_stuff[plr.0 as usize].ptype {
-                PlayerType::Local => "Human",
-                PlayerType::AI => "AI",
-                PlayerType::NotActive => "None",
+            let plr_type = match stuff.player_stuff[plr.0 as usize].ptype {
+                PlayerType::Local => "Human".to_string(),
+                // Only the hard player searches with the multiplayer search
+                PlayerType::AI(policy) if policy.difficulty == Difficulty::Hard =>
+                    format!("AI {} ({}, {})", policy.difficulty.name(), policy.personality.name(), policy.multi.name() ),
+                PlayerType::AI(policy) => format!("AI {} ({})", policy.difficulty.name(), policy.personality.name() ),
+                PlayerType::External(index) => format!("Engine {}", engine_seats.label( index ) ),
+                PlayerType::NotActive => "None".to_string(),
             };
 
-            text.sections[0].value = format!("Player {} -- {}", plr.0 + 1, plrType);
+            let picked = if pad_seat.0 == Some( plr.0 as usize ) { "> " } else { "" };
+            text.sections[0].value = format!("{}Player {} -- {}", picked, plr.0 + 1, plr_type);
+        }
+
+        for mut text in &mut time_q {
+            text.sections[0].value = format!("Time -- {}", clocks.0.control.name() );
         }
 
     }
@@ -1116,25 +2316,23 @@ fn player_settings(
 
 fn player_guidance( 
     //mut commands: Commands,
-    mut stuff: ResMut<GoodStuff>,
+    stuff: Res<GoodStuff>,
     game: Res<GameState>,
     //mut helper_q: Query<(&mut Text, &mut Style), With<PlayerHelp>>,        
     mut helper_q: Query<&mut Text, With<PlayerHelp>>,        
     mut turnicon_q: Query<(&mut Sprite, &TurnIcon)>,        
     mut score_q: Query<(&mut Text, &PlayerScore), Without<PlayerHelp>>,        
-    mut ev_turn: EventReader<TurnAdvance>, ) 
+    mut ev_turn: EventReader<TurnAdvance>,
+    mut ev_over: EventReader<GameOver>, ) 
 {
     for ev in ev_turn.read() {
         
         let mut text = helper_q.single_mut();
-        let pinfo = &mut stuff.player_stuff[ev.0 as usize];
+        let pinfo = &stuff.player_stuff[ev.0 as usize];
         //text.style.color = pinfo.color;
         text.sections[0].style.color = pinfo.color;
 
-        let moves = gen_valid_moves( game.snapshot, ev.0 as usize);
-        if moves.is_empty() {
-            pinfo.out_of_moves = true;
-
+        if pinfo.out_of_moves {
             text.sections[0].value = if pinfo.ptype == PlayerType::Local {            
                 format!("Player {} has no moves and must pass.", ev.0 + 1 )
             } else {
@@ -1143,7 +2341,7 @@ fn player_guidance(
 
         } else {
             text.sections[0].value = if pinfo.ptype == PlayerType::Local {            
-                format!("Player {}'s turn.", ev.0 + 1 )
+                format!("Player {}'s turn.    H: Hint    Wheel/Up/Down while dragging: Exact split    QWEASD/Enter or a controller: Play with buttons", ev.0 + 1 )
             } else {
                 "Waiting for Computer Player".into()
             }
@@ -1161,11 +2359,20 @@ fn player_guidance(
 
             // Update score displays
             for (mut text, score) in &mut score_q {
-                text.sections[0].value = format!( "{:02}", game.snapshot.score[ score.0 as usize ]);
-
+                text.sections[0].value = score_text( &game, score.0 as usize );
             }
         }
+    }
 
+    for ev in ev_over.read() {
+        let mut text = helper_q.single_mut();
+        let result = if ev.winners.len() == 1 {
+            format!("Player {} wins!", ev.winners[0] + 1 )
+        } else {
+            "It's a tie!".to_string()
+        };
+        text.sections[0].value = format!("Game over. {}    A: Analyse the game    R: Rematch    M: Menu", result );
+        text.sections[0].style.color = Color::WHITE;
     }
 }
 
@@ -1196,6 +2403,9 @@ fn on_gamestate_changed(
         {
 
             let spc = gamestate.snapshot.map.spaces[spawn_ndx];
+            if spc.contents != MapSpaceContents::Playable {
+                continue;
+            }
             println!("Added circle at {}, power is {}, player {}", spawn_ndx, spc.power, spc.player  );
 
             // Get the maptile entity that is the parent
@@ -1212,6 +2422,13 @@ fn on_gamestate_changed(
                 None => {}
             }
 
+            // Nothing here any more, which happens when the review winds the board back
+            if spc.power == 0 {
+                let mut vis = q_mapvis.get_mut( ent_vis ).unwrap();
+                vis.circle = None;
+                continue;
+            }
+
             //commands.entity(ent_vis).
             let ring_sz = if spc.power == 1 { 0.9 } else { 1.25 };
 
@@ -1250,115 +2467,798 @@ fn on_gamestate_changed(
     }
 }
 
-fn update_ai( 
-    //mut commands: Commands,    
-    time: Res<Time>,
-    stuff: Res<GoodStuff>,
-    mut q_ai : Query<&mut AIController>,
-    mut ev_turn: EventWriter<TurnAdvance>,    
-    mut ev_gamestate: EventWriter<GameStateChanged>,
-    mut game: ResMut<GameState>, 
-) {
-    let pinfo = &stuff.player_stuff[game.player_turn as usize];
-    let mut should_advance_turn = false;
+// Bitmask of the seats that are playing
+fn active_seats( stuff : &GoodStuff ) -> u8 {
+    let mut seats = 0;
+    for i in 0..stuff.player_stuff.len() {
+        if stuff.player_stuff[i].ptype != PlayerType::NotActive {
+            seats |= 1 << i;
+        }
+    }
+    seats
+}
+
+// Owns the turn order. Plays each turn the input or the AI hands in, then passes
+// the game on to the next seat, marking the end of each round and of the game.
+#[allow(clippy::too_many_arguments)]
+fn turn_manager(
+    mut stuff: ResMut<GoodStuff>,
+    mut game: ResMut<GameState>,
+    mut ev_taken: EventReader<TurnTaken>,
+    mut ev_gamestate: EventWriter<GameStateChanged>,
+    mut ev_turn: EventWriter<TurnAdvance>,
+    mut ev_round: EventWriter<RoundEnded>,
+    mut ev_over: EventWriter<GameOver>,
+    mut clocks: ResMut<MatchClocks>,
+) {
+    for ev in ev_taken.read() {
+        if game.game_over || ev.seat != game.player_turn as usize {
+            println!("Ignoring a turn from player {} out of turn", ev.seat + 1 );
+            continue;
+        }
+
+        // Passing is only for players who can't move
+        let legal = gen_split_moves( &game.snapshot, ev.seat );
+        let allowed = match ev.mv {
+            Some(mv) => legal.contains( &mv ),
+            None => legal.is_empty(),
+        };
+        if !allowed {
+            println!("Ignoring {} from player {}, it isn't legal", turn_text( ev.mv ), ev.seat + 1 );
+            continue;
+        }
+
+        let seats = active_seats( &stuff );
+        end_turn( &mut game, &mut clocks, seats, ev.seat, ev.mv, &mut ev_gamestate, &mut ev_round );
+
+        // Anyone who can't move passes straight away
+        loop {
+            begin_turn( &mut stuff, &mut game, &mut ev_turn, &mut ev_over );
+            let seat = game.player_turn as usize;
+            if game.game_over || !stuff.player_stuff[seat].out_of_moves {
+                break;
+            }
+            end_turn( &mut game, &mut clocks, seats, seat, None, &mut ev_gamestate, &mut ev_round );
+        }
+    }
+}
+
+// Shows the round scores at the end of each round, or the final board once the game is over
+fn follow_turns(
+    mut ev_round: EventReader<RoundEnded>,
+    mut ev_over: EventReader<GameOver>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let round_over = ev_round.read().last().is_some();
+    if ev_over.read().last().is_some() {
+        next_state.set( AppState::GameOver );
+    } else if round_over {
+        next_state.set( AppState::RoundScoring );
+    }
+}
+
+// Lets the AI know when one of the computer players is up. Runs outside of
+// Playing too, so a turn handed out as a round ends isn't missed.
+fn hand_out_turns(
+    stuff: Res<GoodStuff>,
+    mut q_ai : Query<&mut AIController>,
+    mut ev_turn: EventReader<TurnAdvance>,
+) {
+    let mut ai = q_ai.single_mut();
+    for ev in ev_turn.read() {
+        ai.due = matches!( stuff.player_stuff[ev.0 as usize].ptype, PlayerType::AI(_) | PlayerType::External(_) );
+    }
+}
+
+// Plays the seat's move or pass and passes the turn on
+fn end_turn( game : &mut GameState, clocks : &mut MatchClocks, seats : u8, seat : usize, mv : Option<SplitMove>,
+             ev_gamestate : &mut EventWriter<GameStateChanged>, ev_round : &mut EventWriter<RoundEnded> )
+{
+    clocks.0.turn_done( seat );
+
+    match mv {
+        Some(mv) => {
+            game.snapshot.apply_move( mv );
+            game.history.push( (seat, mv) );
+            ev_gamestate.send( GameStateChanged::CircleSplit( mv.from, mv.to ) );
+            ev_gamestate.send( GameStateChanged::CircleAdded( mv.from ) );
+        }
+        None => println!("Player {} passes", seat + 1 ),
+    }
+    game.log.push( (seat, mv) );
+
+    game.player_turn = Position::new( game.snapshot, seat, seats ).next_seat( seat ) as i32;
+    game.turn_num += 1;
+    game.snapshot.update_scores();
+
+    if game.turn_num % game.player_count == 0 {
+        ev_round.send( RoundEnded );
+    }
+}
+
+fn score_text( game : &GameState, seat : usize ) -> String {
+    match game.bonus_points[seat] {
+        0 => format!( "{:02}", game.snapshot.score[seat] ),
+        bonus => format!( "{:02} +{}", game.snapshot.score[seat], bonus ),
+    }
+}
+
+// Hand the turn to whoever's move it is, or end the game if nobody can move
+fn begin_turn( stuff : &mut GoodStuff, game : &mut GameState, ev_turn : &mut EventWriter<TurnAdvance>, ev_over : &mut EventWriter<GameOver> )
+{
+    let seat = game.player_turn as usize;
+    if !game.snapshot.has_moves( seat ) {
+        stuff.player_stuff[seat].out_of_moves = true;
+    }
+    ev_turn.send( TurnAdvance( game.player_turn ) );
+
+    let seats = active_seats( stuff );
+    if Position::new( game.snapshot, seat, seats ).is_game_over() {
+        game.game_over = true;
+
+        // The last round is scored on the spot, even if not everyone got a turn in it
+        let last_round = score_round( &game.snapshot, seats );
+        for (seat, points) in game.bonus_points.iter_mut().enumerate() {
+            *points += last_round.bonus_points( seat );
+        }
+
+        // Most stacks wins, round bonuses break ties
+        let seated = (0..4).filter( |seat| seats & (1 << seat) != 0 );
+        let result = |seat : usize| (game.snapshot.score[seat], game.bonus_points[seat]);
+        let top = seated.clone().map( result ).max().unwrap_or_default();
+        let winners = seated.filter( |seat| result( *seat ) == top ).collect();
+        ev_over.send( GameOver { winners } );
+    }
+}
+
+fn clock_text( left : Duration ) -> String {
+    let secs = left.as_secs();
+    if secs < 10 {
+        format!( "{}.{}", secs, left.subsec_millis() / 100 )
+    } else {
+        format!( "{}:{:02}", secs / 60, secs % 60 )
+    }
+}
+
+// Runs the clock of the player to move. Out of time, they get a random move made
+// for them, or pass if they can't move anyway.
+#[allow(clippy::too_many_arguments)]
+fn update_clocks(
+    time: Res<Time>,
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+    mut clocks: ResMut<MatchClocks>,
+    mut q_ai : Query<&mut AIController>,
+    mut engine_seats: ResMut<EngineSeats>,
+    mut clock_q: Query<(&mut Text, &PlayerClock)>,
+    mut ev_taken: EventWriter<TurnTaken>,
+) {
+    if !clocks.0.is_timed() || game.game_over {
+        return;
+    }
+
+    let seat = game.player_turn as usize;
+    if clocks.0.tick( seat, time.delta() ) {
+        println!("Player {} is out of time", seat + 1 );
+        let mv = gen_split_moves( &game.snapshot, seat ).choose( &mut rand::thread_rng() ).copied();
+        ev_taken.send( TurnTaken { seat, mv } );
+
+        // Whoever was thinking about it can stop now
+        let mut ai = q_ai.single_mut();
+        ai.hurry();
+        ai.turn_timer.reset();
+        if let PlayerType::External(_) = stuff.player_stuff[seat].ptype {
+            engine_seats.abandon( seat );
+        }
+    }
+
+    for (mut text, clock) in &mut clock_q {
+        let seat = clock.0 as usize;
+        text.sections[0].value = if stuff.player_stuff[seat].ptype == PlayerType::NotActive {
+            String::new()
+        } else {
+            clock_text( clocks.0.remaining[seat] )
+        };
+    }
+}
+
+// Seconds the results sit in the middle of the screen, then fly into the frame
+const ROUND_SCORE_HOLD : f32 = 1.0;
+const ROUND_SCORE_FLY : f32 = 0.8;
+
+fn start_round_scoring(
+    mut commands: Commands,
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+    mut scoreboard: ResMut<RoundScoreboard>,
+    turnicon_q: Query<(&GlobalTransform, &TurnIcon)>,
+    frame_q: Query<&GlobalTransform, With<RoundScoringFrame>>,
+) {
+    let seats = active_seats( &stuff );
+    let round = game.turn_num / game.player_count;
+    scoreboard.score = score_round( &game.snapshot, seats );
+    scoreboard.elapsed = 0.0;
+
+    // Everything heads for the icon of the round just played
+    let target = turnicon_q.iter().find( |(_, icon)| icon.0 == round - 1 )
+        .map( |(xform, _)| xform.translation() )
+        .unwrap_or_else( || frame_q.single().translation() );
+
+    let mut lines = vec![ (format!( "Round {}", round ), Color::WHITE) ];
+    for seat in (0..4).filter( |s| seats & (1 << s) != 0 ) {
+        lines.push( (format!( "Player {}: {} stacks", seat + 1, scoreboard.score.stacks[seat] ), stuff.player_stuff[seat].color) );
+    }
+    for (seat, bonus) in &scoreboard.score.bonuses {
+        lines.push( (format!( "Player {}: {} +1", seat + 1, bonus.name() ), stuff.player_stuff[*seat].color) );
+    }
+
+    let mut y = 150.0;
+    for (line, color) in lines {
+        commands.spawn(( Text2dBundle {
+            text: Text::from_section( line, TextStyle {
+                font_size: 36.,
+                color,
+                ..default()
+            }),
+            transform: Transform::from_xyz( 0.0, y, 5.0 ),
+            ..default()
+        }, RoundScorePopup { target } ));
+        y -= 45.0;
+    }
+}
+
+// Plays the results into the frame, then hands the bonuses out and gets on with the game
+fn update_round_scoring(
+    time: Res<Time>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut game: ResMut<GameState>,
+    mut scoreboard: ResMut<RoundScoreboard>,
+    mut popup_q: Query<(&mut Transform, &mut Text, &RoundScorePopup)>,
+    mut score_q: Query<(&mut Text, &PlayerScore), Without<RoundScorePopup>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    scoreboard.elapsed += time.delta_seconds();
+
+    if scoreboard.elapsed > ROUND_SCORE_HOLD {
+        let fade = 1.0 - ((scoreboard.elapsed - ROUND_SCORE_HOLD) / ROUND_SCORE_FLY).min( 1.0 );
+        for (mut xform, mut text, popup) in &mut popup_q {
+            xform.translation = Vec3::lerp( xform.translation, popup.target, 0.1 );
+            xform.scale = Vec3::splat( 0.3 + 0.7 * fade );
+            for section in &mut text.sections {
+                section.style.color.set_a( fade );
+            }
+        }
+    }
+
+    let skip = keyboard_input.any_just_pressed( [ KeyCode::Space, KeyCode::Enter ] );
+    if skip || scoreboard.elapsed > ROUND_SCORE_HOLD + ROUND_SCORE_FLY {
+        for (seat, points) in game.bonus_points.iter_mut().enumerate() {
+            *points += scoreboard.score.bonus_points( seat );
+        }
+        for (mut text, score) in &mut score_q {
+            text.sections[0].value = score_text( &game, score.0 as usize );
+        }
+        next_state.set( AppState::Playing );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_ai( 
+    //mut commands: Commands,    
+    time: Res<Time>,
+    stuff: Res<GoodStuff>,
+    mut q_ai : Query<&mut AIController>,
+    mut ev_taken: EventWriter<TurnTaken>,
+    game: Res<GameState>, 
+    mut engine_seats: ResMut<EngineSeats>,
+    mut debug: ResMut<AIDebug>,
+    clocks: Res<MatchClocks>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    // Nothing more to do once the last move is in
+    if game.game_over {
+        return;
+    }
+
+    let pinfo = &stuff.player_stuff[game.player_turn as usize];
+    let seat = game.player_turn as usize;
+    let mut computer_move = None;
     let mut ai = q_ai.single_mut();
-    if pinfo.ptype == PlayerType::Local && pinfo.out_of_moves {
+
+    // Still thinking about a turn the clock took away, wait for it to give up
+    if ai.is_thinking() && ai.for_turn != game.turn_num {
+        ai.hurry();
+        if ai.poll_thinking().is_none() {
+            return;
+        }
+    }
+
+    // The usual limits, or less if its clock is running down
+    let mut limits = ai.limits;
+    let budget = clocks.0.move_budget( seat );
+    if let Some(budget) = budget {
+        limits.time_budget = Some( limits.time_budget.map_or( budget, |usual| usual.min( budget ) ) );
+    }
+
+    if let PlayerType::AI(policy) = pinfo.ptype {
+        // Start thinking as soon as the turn comes round, the turn timer just makes sure the move isn't instant
+        if ai.due && !ai.is_thinking() {
+            ai.due = false;
+            let pos = Position::new( game.snapshot, game.player_turn as usize, active_seats( &stuff ) );
+            ai.start_thinking( policy, pos, limits );
+            ai.for_turn = game.turn_num;
+            if let Some(budget) = budget {
+                let pause = ai.turn_timer.duration().min( budget );
+                ai.turn_timer.set_duration( pause );
+            }
+        }
+
+        if keyboard_input.just_pressed( KeyCode::Space ) {
+            ai.hurry();
+        }
+
         ai.turn_timer.tick( time.delta());
         if ai.turn_timer.finished() {
-            should_advance_turn = true;
-        }
-    } else if pinfo.ptype == PlayerType::AI {            
-            ai.turn_timer.tick( time.delta());
-            if ai.turn_timer.finished() {
+            if let Some(result) = ai.poll_thinking() {
                 // Take AI Turn
-                let moves = gen_valid_moves( game.snapshot, game.player_turn as usize);
-                if moves.is_empty() {
-                    println!("AI has no valid moves and will pass.");
+                if result.proven {
+                    println!("AI solved the endgame in {} nodes, final margin {}", result.nodes, result.score / WIN_SCORE );
                 } else {
-                    println!("AI has {} valid moves", moves.len() );
-                    // Choose a move a random
-                    let mut rng = rand::thread_rng();
-                    
-                    let old = game.snapshot;
-                    game.snapshot = moves[ rng.gen_range( 0..moves.len())];
-                    let mut currentStrength:i32=-1000000000;
-                    for cMove in moves{
-                        let playerEvals=evaluate_position(cMove);
-                        let mut newStrength:i32=rng.gen_range( 0..1000);
-                        for player in 0..4{
-                            if player==game.player_turn{
-                                newStrength+=playerEvals[player as usize]*(game.player_count-1);
-                            }
-                            else{
-                                newStrength-=playerEvals[player as usize];
-                            }
-                        }
-                        if(newStrength>currentStrength){
-                            currentStrength=newStrength;
-                            game.snapshot=cMove;
-                        }
-                    }
-
-                    // check for splits by looking for decrease
-                    let mut split_ndx = None;
-                    for mapsq in &game.snapshot.map {
-                        let oldsq = old.map.spaces[ mapsq.ndx as usize ];
-                        if oldsq.power > mapsq.power {                            
-                            split_ndx = Some( mapsq.ndx );
-                            break;
-                        }
-                    }                    
-
-                    // Send any adds
-                    for mapsq in &game.snapshot.map {
-                        let oldsq = old.map.spaces[ mapsq.ndx as usize ];
-                        if oldsq.power != mapsq.power {
-                            if split_ndx.is_none() || oldsq.power > mapsq.power {                                
-                                ev_gamestate.send( GameStateChanged::CircleAdded( mapsq.ndx ) );
-                            } else {
-                                ev_gamestate.send( GameStateChanged::CircleSplit( split_ndx.unwrap(), mapsq.ndx ) );
-                            }
-                            
-                            
-                        }
-                    }
+                    println!("AI searched {} nodes to depth {}, score {}", result.nodes, result.depth, result.score );
                 }
+                computer_move = Some( result.best );
+                debug.last_thought = Some( (seat, policy, result) );
+            }
+        }
+    } else if let PlayerType::External(_) = pinfo.ptype {
+        // Same as the AI, but the thinking happens in another program
+        if ai.due {
+            ai.due = false;
+            engine_seats.send_moves( &game.history );
+            engine_seats.go( seat, limits );
+        }
 
-                should_advance_turn = true;                
+        if keyboard_input.just_pressed( KeyCode::Space ) {
+            engine_seats.hurry( seat );
+        }
+
+        ai.turn_timer.tick( time.delta());
+        if ai.turn_timer.finished() {
+            if let Some(answer) = engine_seats.poll( seat ) {
+                let legal = gen_split_moves( &game.snapshot, seat );
+                computer_move = match answer {
+                    Ok(Some(mv)) if legal.contains( &mv ) => Some( Some( mv ) ),
+                    Ok(None) if legal.is_empty() => Some( None ),
+                    answer => {
+                        // Keep the game going rather than let a broken engine hold it up
+                        println!("Engine for player {} answered {:?}, playing a move for it", seat + 1, answer );
+                        let best = greedy_move( &game.snapshot, seat, game.player_count, &EvalWeights::default(), &mut rand::thread_rng() );
+                        Some( best )
+                    }
+                };
             }
+        }
     }
 
-    if should_advance_turn {
-        // Reset turn timer
+    if let Some(mv) = computer_move {
+        ev_taken.send( TurnTaken { seat, mv } );
+
+        // Every turn after the first gets a shorter pause
         ai.turn_timer.reset();
         ai.turn_timer.set_duration( Duration::from_secs_f32( 1.0 ) );
+    }
+}
 
-        // Advance to the next player's turn
-        // todo Wrap this logic up
-        let mut pnum = game.player_turn;
-        loop {
-            pnum = pnum + 1;
-            if pnum >= stuff.player_stuff.len() as i32 {
-                pnum = 0;
+// Keeps the move list up to date and scrolls it
+fn update_game_log(
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut ev_wheel: EventReader<MouseWheel>,
+    mut panel_q: Query<(&Node, &RelativeCursorPosition, &mut Visibility), With<GameLog>>,
+    mut log_q: Query<(&mut GameLogText, &mut Text, &mut Style, &Node)>,
+) {
+    let (panel, cursor, mut visibility) = panel_q.single_mut();
+    let (mut log, mut text, mut style, node) = log_q.single_mut();
+    *visibility = Visibility::Visible;
+
+    if log.shown != game.log.len() {
+        log.shown = game.log.len();
+        text.sections = game.log.iter().enumerate().map( |(turn, (seat, mv))| {
+            TextSection::new( format!( "{:>3}. P{} {}\n", turn + 1, seat + 1, turn_text( *mv ) ),
+                TextStyle {
+                    font_size: 18.,
+                    color: stuff.player_stuff[*seat].color,
+                    ..default()
+                })
+        }).collect();
+    }
+
+    let mut scroll = 0.0;
+    for ev in ev_wheel.read() {
+        if cursor.mouse_over() {
+            scroll += match ev.unit {
+                MouseScrollUnit::Line => ev.y * 20.0,
+                MouseScrollUnit::Pixel => ev.y,
+            };
+        }
+    }
+    if keyboard_input.just_pressed( KeyCode::PageUp ) {
+        scroll += panel.size().y * 0.8;
+    }
+    if keyboard_input.just_pressed( KeyCode::PageDown ) {
+        scroll -= panel.size().y * 0.8;
+    }
+
+    let max_scroll = (node.size().y - panel.size().y).max( 0.0 );
+    if scroll != 0.0 {
+        log.position = (log.position - scroll).clamp( 0.0, max_scroll );
+        log.follow = log.position >= max_scroll - 1.0;
+    } else if log.follow {
+        log.position = max_scroll;
+    }
+    style.top = Val::Px( -log.position );
+}
+
+// Animate the prompt while the computer works out its move
+fn show_ai_thinking(
+    time: Res<Time>,
+    q_ai : Query<&AIController>,
+    engine_seats: Res<EngineSeats>,
+    mut helper_q: Query<&mut Text, With<PlayerHelp>>,
+) {
+    let ai = q_ai.single();
+    if ai.is_thinking() || engine_seats.thinking {
+        let dots = (time.elapsed_seconds() * 3.0) as usize % 4;
+        let mut text = helper_q.single_mut();
+        text.sections[0].value = format!("Computer Player is thinking{:<3}  (Space to hurry)", ".".repeat( dots ));
+    }
+}
+
+// H asks the AI what it would play for the human whose turn it is
+fn update_hint(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    stuff: Res<GoodStuff>,
+    game: Res<GameState>,
+    mut stats: ResMut<MatchStats>,
+    mut hint_q: Query<&mut HintFinder>,
+    mut helper_q: Query<&mut Text, With<PlayerHelp>>,
+) {
+    let mut hint = hint_q.single_mut();
+    let player = game.player_turn as usize;
+
+    // Old hints are no good once the turn has moved on
+    if hint.for_turn != game.turn_num {
+        hint.suggestion = None;
+        if hint.search.is_thinking() {
+            hint.search.hurry();
+        }
+    }
+
+    if hint.search.is_thinking() {
+        let result = hint.search.poll_thinking();
+        if hint.for_turn == game.turn_num {
+            let mut text = helper_q.single_mut();
+            match result {
+                None => text.sections[0].value = "Looking for a hint...".into(),
+                Some(result) => {
+                    hint.suggestion = result.best;
+                    text.sections[0].value = format!("Player {}'s turn.    Hints used: {}", player + 1, stats.hints_used[player] );
+                }
             }
+        }
+        return;
+    }
 
-            if stuff.player_stuff[pnum as usize].ptype != PlayerType::NotActive {
-                break;
+    let pinfo = &stuff.player_stuff[player];
+    if keyboard_input.just_pressed( KeyCode::KeyH ) && pinfo.ptype == PlayerType::Local && !pinfo.out_of_moves
+        && hint.suggestion.is_none() {
+        stats.hints_used[player] += 1;
+        hint.for_turn = game.turn_num;
+        let pos = Position::new( game.snapshot, player, active_seats( &stuff ) );
+        let limits = hint.search.limits;
+        hint.search.start_thinking( AIPolicy { difficulty : Difficulty::Hard, ..default() }, pos, limits );
+    }
+}
+
+fn review_summary( moves : &[MoveReview], seats : u8 ) -> String {
+    let mut summary = String::from("Mistakes/blunders ");
+    for seat in (0..4).filter( |seat| seats & (1 << seat) != 0 ) {
+        let count = |judgement| moves.iter().filter( |r| r.seat() == seat && r.judgement == judgement ).count();
+        summary += &format!(" P{} {}/{}", seat + 1, count( Judgement::Mistake ), count( Judgement::Blunder ) );
+    }
+    summary + "    Left/Right: Step through moves    N: Next mistake    Esc: Final position    R: Rematch    M: Menu"
+}
+
+fn describe_move( ply : usize, review : &MoveReview ) -> String {
+    let mut text = format!("Move {}: Player {} split {}.", ply + 1, review.seat() + 1, review.played.amount );
+    if review.judgement != Judgement::Good {
+        text += &format!(" {}, {:.1} stacks worse than the best move.", review.judgement.name(), review.loss() as f32 / STACK_SCORE as f32 );
+    } else if review.better_move().is_some() {
+        text += " Fine, the best move is shown in white.";
+    } else {
+        text += " Best move.";
+    }
+    if review.proven {
+        text += &format!(" Solved: it finishes {:+} stacks against the best opponent.", review.played_score / STACK_SCORE );
+    }
+    text
+}
+
+// Once the game is over: A runs the analysis, then the arrow keys step through it
+fn update_review(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    stuff: Res<GoodStuff>,
+    mut game: ResMut<GameState>,
+    mut review: ResMut<Review>,
+    mut helper_q: Query<&mut Text, With<PlayerHelp>>,
+    mut ev_gamestate: EventWriter<GameStateChanged>,
+) {
+    let mut text = helper_q.single_mut();
+    let seats = active_seats( &stuff );
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(task) = review.task.as_mut() {
+        match block_on( poll_once( task ) ) {
+            Some(moves) => {
+                review.task = None;
+                text.sections[0].value = review_summary( &moves, seats );
+                review.moves = moves;
+            }
+            None => {
+                let done = review.progress.load( Ordering::Relaxed );
+                text.sections[0].value = format!("Analysing move {} of {}...", done + 1, game.history.len() );
+                return;
             }
+        }
+    }
 
-            if pnum == game.player_turn {
-                println!("Didn't find any active players?");
-                break;
+    if review.moves.is_empty() {
+        if keyboard_input.just_pressed( KeyCode::KeyA ) && !game.history.is_empty() {
+            review.final_snapshot = game.snapshot;
+            review.viewing = None;
+            review.progress.store( 0, Ordering::Relaxed );
+
+            let start = Position::new( game.start, 0, seats );
+            let history = game.history.clone();
+            let progress = review.progress.clone();
+            let work = move || Reviewer::default().review_game( start, &history, &mut |done| progress.store( done, Ordering::Relaxed ) );
+
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                review.task = Some( AsyncComputeTaskPool::get().spawn( async move { work() } ) );
+            }
+            #[cfg(target_arch = "wasm32")]
+            {
+                review.moves = work();
+                text.sections[0].value = review_summary( &review.moves, seats );
             }
         }
-        game.player_turn = pnum;
-        game.turn_num += 1;
+        return;
+    }
 
-        game.snapshot.update_scores();
+    let last = review.moves.len() - 1;
+    let mut viewing = review.viewing;
+    if keyboard_input.just_pressed( KeyCode::ArrowLeft ) {
+        viewing = Some( viewing.map_or( last, |ply| ply.saturating_sub( 1 ) ) );
+    }
+    if keyboard_input.just_pressed( KeyCode::ArrowRight ) {
+        viewing = viewing.and_then( |ply| if ply < last { Some( ply + 1 ) } else { None } );
+    }
+    if keyboard_input.just_pressed( KeyCode::KeyN ) {
+        let from = viewing.map_or( 0, |ply| ply + 1 );
+        if let Some(ply) = (from..=last).find( |ply| review.moves[*ply].judgement != Judgement::Good ) {
+            viewing = Some( ply );
+        }
+    }
+    if keyboard_input.just_pressed( KeyCode::Escape ) {
+        viewing = None;
+    }
+
+    if viewing != review.viewing {
+        review.viewing = viewing;
+
+        // Wind the board back to just before the move, using the normal rendering
+        game.snapshot = match viewing {
+            Some(ply) => review.moves[ply].before.snapshot,
+            None => review.final_snapshot,
+        };
+        for space in &game.snapshot.map {
+            ev_gamestate.send( GameStateChanged::CircleAdded( space.ndx ) );
+        }
 
-        ev_turn.send( TurnAdvance(pnum) );
+        match viewing {
+            Some(ply) => {
+                let seat = review.moves[ply].seat();
+                text.sections[0].value = describe_move( ply, &review.moves[ply] );
+                text.sections[0].style.color = stuff.player_stuff[seat].color;
+            }
+            None => {
+                text.sections[0].value = review_summary( &review.moves, seats );
+                text.sections[0].style.color = Color::WHITE;
+            }
+        }
+    }
+}
+
+// The move that was played, and a graph of how each player's position went
+fn draw_review(
+    review: Res<Review>,
+    stuff: Res<GoodStuff>,
+    windows: Query<&Window>,
+    mut gizmos: Gizmos,
+    mut graph: Gizmos<ReviewGraphGizmos>,
+) {
+    if review.moves.is_empty() {
+        return;
+    }
+
+    if let Some(ply) = review.viewing {
+        let played = review.moves[ply].played;
+        let offs = Vec3 { x : 0.0, y : 0.3, z : 0.0 };
+        let color = stuff.player_stuff[ review.moves[ply].seat() ].color;
+        gizmos.arrow( worldpos_from_mapindex( played.from ) + offs, worldpos_from_mapindex( played.to ) + offs, color );
+    }
+
+    // Along the bottom of the screen, in 2D camera space (pixels, origin in the middle)
+    let window = windows.single();
+    let width = (window.width() * 0.6).min( 700.0 );
+    let height = 120.0;
+    let left = -width / 2.0;
+    let mid = -window.height() / 2.0 + 60.0 + height / 2.0;
+    let step = width / review.moves.len().max( 1 ) as f32;
+
+    // Scores in stacks, from the point of view of whoever was moving
+    let point = |ply : usize| {
+        let stacks = (review.moves[ply].best_score as f32 / STACK_SCORE as f32).clamp( -10.0, 10.0 );
+        Vec2::new( left + (ply as f32 + 0.5) * step, mid + stacks / 10.0 * height / 2.0 )
+    };
+
+    graph.line_2d( Vec2::new( left, mid ), Vec2::new( left + width, mid ), Color::rgba( 1.0, 1.0, 1.0, 0.3 ) );
+    for seat in 0..4 {
+        let plies = (0..review.moves.len()).filter( |ply| review.moves[*ply].seat() == seat );
+        graph.linestrip_2d( plies.map( point ), stuff.player_stuff[seat].color );
+    }
+
+    for (ply, move_review) in review.moves.iter().enumerate() {
+        match move_review.judgement {
+            Judgement::Mistake => { graph.circle_2d( point( ply ), 4.0, Color::ORANGE ); }
+            Judgement::Blunder => { graph.circle_2d( point( ply ), 6.0, Color::RED ); }
+            Judgement::Good => {}
+        }
+    }
+
+    if let Some(ply) = review.viewing {
+        let x = point( ply ).x;
+        graph.line_2d( Vec2::new( x, mid - height / 2.0 ), Vec2::new( x, mid + height / 2.0 ), Color::WHITE );
+    }
+}
+
+fn setup_ai_debug( mut commands: Commands )
+{
+    let style = TextStyle { font_size: 18., ..default() };
+    commands.spawn((
+        TextBundle {
+            visibility: Visibility::Hidden,
+            ..TextBundle::from_section( "", style.clone() )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(12.0),
+                left: Val::Px(90.0),
+                ..default()
+            })
+        },
+        DebugPanel,
+    ));
+
+    for ndx in 0..gamestate::MAP_SZ * gamestate::MAP_SZ {
+        commands.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                ..TextBundle::from_section( "", style.clone() )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                })
+            },
+            DebugStackLabel(ndx),
+        ));
+    }
+}
+
+fn debug_score_text( score : i32 ) -> String {
+    if score.abs() >= WIN_SCORE {
+        format!("ends {:+}", score / WIN_SCORE )
+    } else {
+        format!("{:+}", score )
+    }
+}
+
+// The text of the debug panel and whether it shows
+type DebugPanelView = (&'static mut Text, &'static mut Visibility);
+
+// Rings on every empty hex in the colors of the players that can split onto it,
+// each stack's weight in stacks, and the last computer player's candidate moves
+#[allow(clippy::too_many_arguments)]
+fn draw_ai_debug(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut debug: ResMut<AIDebug>,
+    game: Res<GameState>,
+    stuff: Res<GoodStuff>,
+    q_ai : Query<&AIController>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    mut label_q: Query<(&DebugStackLabel, &mut Text, &mut Style, &mut Visibility)>,
+    mut panel_q: Query<DebugPanelView, (With<DebugPanel>, Without<DebugStackLabel>)>,
+    mut gizmos: Gizmos,
+) {
+    if keyboard_input.just_pressed( KeyCode::F3 ) {
+        debug.shown = !debug.shown;
+    }
+
+    let (mut panel, mut panel_vis) = panel_q.single_mut();
+    if !debug.shown {
+        *panel_vis = Visibility::Hidden;
+        for (_, _, _, mut vis) in &mut label_q {
+            *vis = Visibility::Hidden;
+        }
+        return;
+    }
+
+    // The board as the player to move sees it, with their personality if it's a computer
+    let base_weights = q_ai.single().base_weights;
+    let weights = match stuff.player_stuff[ game.player_turn as usize ].ptype {
+        PlayerType::AI(policy) => policy.personality.adjust( base_weights ),
+        _ => base_weights,
+    };
+    let breakdown = explain_position_with( game.snapshot, &weights );
+
+    for space in &game.snapshot.map {
+        if space.contents != MapSpaceContents::Playable || space.power != 0 {
+            continue;
+        }
+        let pos = worldpos_from_mapindex( space.ndx ) + Vec3::Y * 0.2;
+        let mut radius = 0.55;
+        for player in 0..4 {
+            if breakdown.access_map[ space.ndx as usize ] & (1 << player) != 0 {
+                gizmos.circle( pos, Direction3d::Y, radius, stuff.player_stuff[player].color );
+                radius -= 0.1;
+            }
+        }
+    }
+
+    let (camera, camera_global_transform) = camera_q.single();
+    for (label, mut text, mut style, mut vis) in &mut label_q {
+        let space = game.snapshot.map.spaces[ label.0 ];
+        let viewport_position = camera.world_to_viewport( camera_global_transform, worldpos_from_mapindex( space.ndx ) );
+        match viewport_position {
+            Some(viewport_position) if space.power > 0 => {
+                text.sections[0].value = format!("{:.1}", breakdown.stack_weight[ label.0 ] as f32 / STACK_SCORE as f32 );
+                style.top = Val::Px( viewport_position.y + 12.0 );
+                style.left = Val::Px( viewport_position.x - 12.0 );
+                *vis = Visibility::Visible;
+            }
+            _ => *vis = Visibility::Hidden,
+        }
+    }
+
+    let mut lines = vec![ "AI debug (F3 to hide). Rings: who can reach the hex, numbers: stack weight".to_string() ];
+    let seats = active_seats( &stuff );
+    let evals : Vec<String> = (0..4).filter( |seat| seats & (1 << seat) != 0 )
+        .map( |seat| format!("P{} {:.1}", seat + 1, breakdown.scores[seat] as f32 / STACK_SCORE as f32 ) )
+        .collect();
+    lines.push( format!("Evaluation: {}", evals.join( "  " ) ) );
+
+    if let Some((seat, policy, result)) = &debug.last_thought {
+        lines.push( format!("Player {} ({} {}) last move, depth {}, {} nodes:",
+            seat + 1, policy.difficulty.name(), policy.personality.name(), result.depth, result.nodes ) );
+        for (mv, score) in &result.candidates {
+            let chosen = if Some( *mv ) == result.best { "*" } else { " " };
+            lines.push( format!(" {} {:<10} {}", chosen, move_text( *mv ), debug_score_text( *score ) ) );
+        }
     }
 
+    panel.sections[0].value = lines.join( "\n" );
+    *panel_vis = Visibility::Visible;
 }
 
 fn update_ui( 
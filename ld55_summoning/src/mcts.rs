@@ -0,0 +1,331 @@
+// Monte Carlo Tree Search player.
+//
+// UCT over the split moves, with playouts run to the end of the game. Rewards are
+// kept per seat so the same tree works for any number of players: each node is
+// judged from the point of view of whoever chose the move leading to it.
+//
+// Nodes only store the move that got there, positions are rebuilt by replaying
+// moves down from the root. The tree is kept between turns, if the next position
+// we are asked about is somewhere below the old root that subtree becomes the new root.
+
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use web_time::{Duration, Instant};
+
+use crate::evaluator::Evaluator;
+use crate::gamestate::{gen_split_moves, EvalWeights, Position, SplitMove};
+use crate::movegen::MoveGen;
+use crate::multisearch::relative_scores;
+use crate::search::{SearchResult, StopFlag, MAX_CANDIDATES};
+
+// How many candidate moves a guided playout looks at before picking the best looking one
+const GUIDED_SAMPLES : usize = 4;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Playout {
+    #[default]
+    Random,
+    Guided,  // best of a few random moves according to evaluate_position
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MctsBudget {
+    pub iterations : Option<u32>,
+    pub time_budget : Option<Duration>,
+}
+
+impl MctsBudget {
+    pub fn iterations( iterations : u32 ) -> MctsBudget {
+        MctsBudget { iterations : Some( iterations ), time_budget : None }
+    }
+
+    pub fn time( budget : Duration ) -> MctsBudget {
+        MctsBudget { iterations : None, time_budget : Some( budget ) }
+    }
+}
+
+impl Default for MctsBudget {
+    fn default() -> Self {
+        MctsBudget { iterations : Some( 200_000 ), time_budget : Some( Duration::from_millis( 1500 ) ) }
+    }
+}
+
+struct Node {
+    mv : Option<SplitMove>,
+    parent : Option<usize>,
+    children : Vec<usize>,
+    untried : Option<Vec<SplitMove>>,  // filled in the first time the node is expanded
+    to_move : usize,
+    visits : u32,
+    reward : [f32; 4],
+}
+
+impl Node {
+    fn new( mv : Option<SplitMove>, parent : Option<usize>, to_move : usize ) -> Node {
+        Node { mv, parent, children : Vec::new(), untried : None, to_move, visits : 0, reward : [0.0; 4] }
+    }
+}
+
+pub struct Mcts {
+    pub playout : Playout,
+    pub exploration : f32,
+    pub evaluator : Arc<dyn Evaluator>,  // only used by guided playouts
+    pub stop : StopFlag,
+    pub move_gen : MoveGen,  // for the tree, playouts always pick from every move
+    nodes : Vec<Node>,
+    root_pos : Option<Position>,
+    rng : StdRng,
+}
+
+impl Default for Mcts {
+    fn default() -> Self {
+        Mcts::new( Playout::default(), rand::thread_rng().gen() )
+    }
+}
+
+impl Mcts {
+    pub fn new( playout : Playout, seed : u64 ) -> Mcts {
+        Mcts {
+            playout,
+            exploration : 1.4,
+            evaluator : Arc::new( EvalWeights::default() ),
+            stop : StopFlag::default(),
+            move_gen : MoveGen::default(),
+            nodes : Vec::new(),
+            root_pos : None,
+            rng : StdRng::seed_from_u64( seed ),
+        }
+    }
+
+    pub fn clear( &mut self ) {
+        self.nodes.clear();
+        self.root_pos = None;
+    }
+
+    pub fn search( &mut self, pos : &Position, budget : MctsBudget ) -> SearchResult {
+        let mut result = SearchResult::default();
+        if gen_split_moves( &pos.snapshot, pos.to_move ).is_empty() {
+            return result;
+        }
+
+        if !self.reuse_tree( pos ) {
+            self.nodes.clear();
+            self.nodes.push( Node::new( None, None, pos.to_move ) );
+            self.root_pos = Some( *pos );
+        }
+
+        // With no limit at all it would never stop, fall back on the default count
+        let max_iter = match (budget.iterations, budget.time_budget) {
+            (None, None) => MctsBudget::default().iterations,
+            (iterations, _) => iterations,
+        };
+        let deadline = budget.time_budget.map( |budget| Instant::now() + budget );
+        let mut iterations = 0;
+        loop {
+            // Always at least one, so the move comes from the tree
+            self.iterate( pos );
+            iterations += 1;
+
+            if let Some(max_iter) = max_iter {
+                if iterations >= max_iter {
+                    break;
+                }
+            }
+            // Instant::now() is cheap enough next to a playout
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    break;
+                }
+            }
+            if self.stop.is_stopped() {
+                break;
+            }
+        }
+
+        // Pick the most visited move, it is the most robust choice
+        let root = &self.nodes[0];
+        let best = root.children.iter().max_by_key( |c| self.nodes[**c].visits );
+        match best {
+            Some(&child) => {
+                let node = &self.nodes[child];
+                result.best = node.mv;
+                result.score = (1000.0 * node.reward[pos.to_move] / node.visits.max( 1 ) as f32) as i32;
+            }
+            None => {
+                // The one iteration didn't get to expand the root, just play something legal
+                result.best = gen_split_moves( &pos.snapshot, pos.to_move ).first().copied();
+            }
+        }
+        // In the same order they were picked by, most visited first
+        let mut children = root.children.clone();
+        children.sort_by_key( |c| std::cmp::Reverse( self.nodes[*c].visits ) );
+        result.candidates = children.iter().take( MAX_CANDIDATES ).filter_map( |c| {
+            let node = &self.nodes[*c];
+            Some( (node.mv?, (1000.0 * node.reward[pos.to_move] / node.visits.max( 1 ) as f32) as i32) )
+        }).collect();
+        result.nodes = iterations as u64;
+        result.depth = self.max_depth( 0 );
+        result
+    }
+
+    // Look up to one full rotation of seats below the old root for the new position
+    fn reuse_tree( &mut self, pos : &Position ) -> bool {
+        let Some(root_pos) = self.root_pos else {
+            return false;
+        };
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let target = pos.hash();
+        let mut frontier = vec![ (0, root_pos) ];
+        for _ in 0..=pos.seat_count() {
+            let mut next_frontier = Vec::new();
+            for (ndx, node_pos) in frontier {
+                if node_pos.hash() == target && node_pos.to_move == pos.to_move {
+                    self.reroot( ndx );
+                    self.root_pos = Some( *pos );
+                    return true;
+                }
+                for &child in &self.nodes[ndx].children {
+                    let mv = self.nodes[child].mv.unwrap();
+                    next_frontier.push( (child, node_pos.play( mv )) );
+                }
+            }
+            frontier = next_frontier;
+        }
+        false
+    }
+
+    // Copy the subtree under `new_root` into a fresh arena
+    fn reroot( &mut self, new_root : usize ) {
+        let mut old = std::mem::take( &mut self.nodes );
+        let mut stack = vec![ (new_root, None) ];
+        while let Some((old_ndx, parent)) = stack.pop() {
+            let mut node = std::mem::replace( &mut old[old_ndx], Node::new( None, None, 0 ) );
+            let new_ndx = self.nodes.len();
+            let children = std::mem::take( &mut node.children );
+            node.parent = parent;
+            if parent.is_none() {
+                node.mv = None;
+            }
+            self.nodes.push( node );
+            if let Some(parent) = parent {
+                self.nodes[parent].children.push( new_ndx );
+            }
+            for child in children {
+                stack.push( (child, Some( new_ndx )) );
+            }
+        }
+    }
+
+    fn iterate( &mut self, root_pos : &Position ) {
+        // Selection
+        let mut ndx = 0;
+        let mut pos = *root_pos;
+        loop {
+            if pos.is_game_over() {
+                break;
+            }
+            let node = &mut self.nodes[ndx];
+            let move_gen = self.move_gen;
+            let untried = node.untried.get_or_insert_with( || {
+                let mut moves = move_gen.moves( &pos.snapshot, pos.to_move );
+                moves.shuffle( &mut self.rng );
+                moves
+            });
+
+            // Expansion
+            if let Some(mv) = untried.pop() {
+                pos = pos.play( mv );
+                let child = self.nodes.len();
+                self.nodes.push( Node::new( Some( mv ), Some( ndx ), pos.to_move ) );
+                self.nodes[ndx].children.push( child );
+                ndx = child;
+                break;
+            }
+
+            let child = self.select_child( ndx );
+            pos = pos.play( self.nodes[child].mv.unwrap() );
+            ndx = child;
+        }
+
+        // Simulation
+        let reward = self.playout( pos );
+
+        // Backpropagation
+        let mut curr = Some( ndx );
+        while let Some(n) = curr {
+            let node = &mut self.nodes[n];
+            node.visits += 1;
+            for (total, r) in node.reward.iter_mut().zip( reward ) {
+                *total += r;
+            }
+            curr = node.parent;
+        }
+    }
+
+    // UCT, each child is scored for the seat that gets to pick it
+    fn select_child( &self, ndx : usize ) -> usize {
+        let node = &self.nodes[ndx];
+        let seat = node.to_move;
+        let log_n = (node.visits.max( 1 ) as f32).ln();
+        let mut best = node.children[0];
+        let mut best_value = f32::MIN;
+        for &c in &node.children {
+            let child = &self.nodes[c];
+            let visits = child.visits.max( 1 ) as f32;
+            let value = child.reward[seat] / visits + self.exploration * (log_n / visits).sqrt();
+            if value > best_value {
+                best_value = value;
+                best = c;
+            }
+        }
+        best
+    }
+
+    // Play to the end and share out a point between the winners
+    fn playout( &mut self, mut pos : Position ) -> [f32; 4] {
+        while !pos.is_game_over() {
+            let moves = gen_split_moves( &pos.snapshot, pos.to_move );
+            let mv = match self.playout {
+                Playout::Random => *moves.choose( &mut self.rng ).unwrap(),
+                Playout::Guided => {
+                    let mut best = None;
+                    let mut best_value = i32::MIN;
+                    for _ in 0..GUIDED_SAMPLES {
+                        let mv = *moves.choose( &mut self.rng ).unwrap();
+                        let value = relative_scores( &pos.play( mv ), self.evaluator.as_ref() )[pos.to_move];
+                        if value > best_value {
+                            best_value = value;
+                            best = Some( mv );
+                        }
+                    }
+                    best.unwrap()
+                }
+            };
+            pos = pos.play( mv );
+        }
+
+        let scores : Vec<i32> = (0..4).map( |seat| {
+            if pos.is_seated( seat ) { pos.snapshot.calc_simple_score( seat as i32 ) } else { -1 }
+        }).collect();
+        let top = *scores.iter().max().unwrap();
+        let winners = scores.iter().filter( |s| **s == top ).count() as f32;
+
+        let mut reward = [0.0; 4];
+        for (r, score) in reward.iter_mut().zip( scores ) {
+            if score == top {
+                *r = 1.0 / winners;
+            }
+        }
+        reward
+    }
+
+    fn max_depth( &self, ndx : usize ) -> u32 {
+        self.nodes[ndx].children.iter().map( |c| 1 + self.max_depth( *c ) ).max().unwrap_or( 0 )
+    }
+}
@@ -0,0 +1,258 @@
+// Reduced move generation.
+//
+// gen_split_moves gives every amount from 1 to power-1 in every direction, but late
+// in the game most of those amounts lead to positions that play out exactly the
+// same. This generator keeps one move out of each group of equivalent ones.
+//
+// Why it is safe: a stack's family (the stack plus everything later split off it)
+// can only ever land on empty hexes in the empty regions next to the stack, and
+// those regions only shrink. Call the number of such hexes E. A stack with power
+// of at least 2^E can't run out of power before it runs out of room, so any two
+// powers at or above 2^E are interchangeable:
+//  - E = 0, the stack is boxed in and will never move, its power doesn't matter.
+//  - Otherwise, whatever split one of the two stacks makes, the other can make a
+//    split to the same hex where each half either has the same power as its twin
+//    or is at least 2^(E-1), and both halves have at most E-1 empty hexes left.
+//    Opponent moves only take room away, so that never breaks the argument.
+// Occupancy, move lists and final scores are identical either way, so positions
+// that only differ in the power of such stacks have the same value for every
+// player, whatever the number of seats or the search used.
+//
+// Moves to different hexes always leave different hexes filled, and no two
+// directions from a stack reach the same hex, so the only duplicates are amounts
+// for the same source and target. Those are bucketed by the capped power of both
+// halves, and the smallest amount in each bucket is kept.
+//
+// The regions are only found once per position, before the target is filled.
+// Filling it takes one hex out of the region it is in, which both halves border,
+// and can only cut the rest into smaller pieces, so the room before less one is
+// never short of the real room. Overestimating room only means fewer amounts
+// share a bucket, so that stays safe.
+//
+// The maps are random, so there are no board symmetries worth looking for.
+//
+// Regions stay big until late in the game, so this only starts to cut moves once
+// the board is nearly full. The bucketed generator goes further by only keeping a
+// handful of amounts in each direction. That isn't exact, a search using it can
+// miss the best split, but big stacks go from up to 19 amounts a direction to 7.
+
+use crate::gamestate::{gen_split_moves, GameSnapshot, MapDirection, MapSpaceContents, SplitMove, INVALID, MAP_SZ};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MoveGen {
+    #[default]
+    Full,
+    Reduced,
+    Bucketed,  // not exact, see gen_bucketed_moves
+}
+
+impl MoveGen {
+    pub fn moves( &self, snapshot : &GameSnapshot, for_player : usize ) -> Vec<SplitMove> {
+        match self {
+            MoveGen::Full => gen_split_moves( snapshot, for_player ),
+            MoveGen::Reduced => gen_reduced_moves( snapshot, for_player ),
+            MoveGen::Bucketed => gen_bucketed_moves( snapshot, for_player ),
+        }
+    }
+}
+
+const NO_REGION : u8 = u8::MAX;
+
+// The empty hexes split into connected regions
+struct Regions {
+    id : [u8; MAP_SZ * MAP_SZ],
+    size : Vec<u32>,
+}
+
+impl Regions {
+    fn find( snapshot : &GameSnapshot ) -> Regions {
+        let is_empty = |ndx : i32| {
+            let space = &snapshot.map.spaces[ndx as usize];
+            space.contents == MapSpaceContents::Playable && space.power == 0
+        };
+
+        let mut regions = Regions { id : [NO_REGION; MAP_SZ * MAP_SZ], size : Vec::new() };
+        let mut stack = Vec::new();
+        for start in 0..(MAP_SZ * MAP_SZ) as i32 {
+            if !is_empty( start ) || regions.id[start as usize] != NO_REGION {
+                continue;
+            }
+            let region = regions.size.len() as u8;
+            let mut size = 0;
+            regions.id[start as usize] = region;
+            stack.push( start );
+            while let Some(ndx) = stack.pop() {
+                size += 1;
+                for nbr in snapshot.map.neighbors( ndx, true ) {
+                    if is_empty( nbr ) && regions.id[nbr as usize] == NO_REGION {
+                        regions.id[nbr as usize] = region;
+                        stack.push( nbr );
+                    }
+                }
+            }
+            regions.size.push( size );
+        }
+        regions
+    }
+
+    // Empty hexes a stack at `ndx` and anything split off it could ever reach
+    fn room( &self, snapshot : &GameSnapshot, ndx : i32 ) -> u32 {
+        let mut seen : Vec<u8> = Vec::new();
+        for nbr in snapshot.map.neighbors( ndx, true ) {
+            let region = self.id[nbr as usize];
+            if region != NO_REGION && !seen.contains( &region ) {
+                seen.push( region );
+            }
+        }
+        seen.iter().map( |r| self.size[*r as usize] ).sum()
+    }
+
+    // Room for each half of a split from `from` to `to`, once `to` is filled
+    fn split_room( &self, snapshot : &GameSnapshot, from : i32, to : i32 ) -> (u32, u32) {
+        let source_room = self.room( snapshot, from ).saturating_sub( 1 );
+        let dest_room = self.size[ self.id[to as usize] as usize ].saturating_sub( 1 );
+        (source_room, dest_room)
+    }
+}
+
+// Power above 2^room makes no difference to how the game can go
+pub fn capped_power( power : u8, room : u32 ) -> u8
+{
+    if room >= 8 {
+        power
+    } else {
+        power.min( 1 << room )
+    }
+}
+
+pub fn gen_reduced_moves( gamecurr : &GameSnapshot, for_player : usize ) -> Vec<SplitMove>
+{
+    gen_moves_from( gamecurr, for_player, |power| (1..power).collect() )
+}
+
+// The amounts worth looking at for a stack: a little, a lot, and a few in between
+fn amount_buckets( power : u8 ) -> Vec<u8>
+{
+    let mut amounts = vec![ 1, 2, power / 4, power / 2, power - power / 4, power - 2, power - 1 ];
+    amounts.retain( |amount| *amount >= 1 && *amount < power );
+    amounts.sort();
+    amounts.dedup();
+    amounts
+}
+
+// The reduced moves, but only trying the amounts from amount_buckets. Not exact.
+pub fn gen_bucketed_moves( gamecurr : &GameSnapshot, for_player : usize ) -> Vec<SplitMove>
+{
+    gen_moves_from( gamecurr, for_player, amount_buckets )
+}
+
+// One move for each bucket of equivalent amounts among those `amounts` gives for
+// the power of the stack, in the order given
+fn gen_moves_from( gamecurr : &GameSnapshot, for_player : usize, amounts : impl Fn( u8 ) -> Vec<u8> ) -> Vec<SplitMove>
+{
+    let mut result = Vec::new();
+    let mut buckets : Vec<(u8, u8)> = Vec::new();
+    let regions = Regions::find( gamecurr );
+
+    for mapsq in &gamecurr.map {
+        if (mapsq.power > 1) && (mapsq.player == (for_player + 1) as u8) {
+            let stack_amounts = amounts( mapsq.power );
+            for mapdir in MapDirection::iterator() {
+                let ndx = mapsq.ndx;
+                let move_ndx = gamecurr.map.search_dir( ndx, mapdir );
+                if move_ndx == ndx || move_ndx == INVALID as i32 {
+                    continue;
+                }
+
+                // Room left for both halves once the target is filled
+                let (source_room, dest_room) = regions.split_room( gamecurr, ndx, move_ndx );
+
+                buckets.clear();
+                for &amount in &stack_amounts {
+                    let bucket = ( capped_power( mapsq.power - amount, source_room ), capped_power( amount, dest_room ) );
+                    if !buckets.contains( &bucket ) {
+                        buckets.push( bucket );
+                        result.push( SplitMove { from : ndx, to : move_ndx, amount } );
+                    }
+                }
+            }
+        }
+    }
+
+    result
+}
+
+// The move gen_reduced_moves keeps in place of `mv`, for checking the reduction
+pub fn representative( gamecurr : &GameSnapshot, mv : SplitMove ) -> SplitMove
+{
+    let power = gamecurr.map.spaces[mv.from as usize].power;
+    let (source_room, dest_room) = Regions::find( gamecurr ).split_room( gamecurr, mv.from, mv.to );
+    let bucket = |amount : u8| ( capped_power( power - amount, source_room ), capped_power( amount, dest_room ) );
+
+    let amount = (1..power).find( |a| bucket( *a ) == bucket( mv.amount ) ).unwrap();
+    SplitMove { amount, ..mv }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::gamestate::{generate_map, Position};
+
+    // Every position along a few random games, for two to four seats
+    fn random_positions() -> Vec<Position> {
+        let mut positions = Vec::new();
+        for (seed, seats) in [ (1, 0b0011), (2, 0b0011), (3, 0b0111), (4, 0b1111) ] {
+            let mut rng = StdRng::seed_from_u64( seed );
+            let mut pos = Position::new( generate_map( seats, &mut rng ), 3, seats );
+            pos.advance();
+            while !pos.is_game_over() {
+                positions.push( pos );
+                pos = pos.play( *gen_split_moves( &pos.snapshot, pos.to_move ).choose( &mut rng ).unwrap() );
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn reduced_moves_are_real_moves() {
+        for pos in random_positions() {
+            let full = gen_split_moves( &pos.snapshot, pos.to_move );
+            let reduced = gen_reduced_moves( &pos.snapshot, pos.to_move );
+            assert!( !reduced.is_empty() );
+            for mv in &reduced {
+                assert!( full.contains( mv ), "{:?} isn't a legal move", mv );
+            }
+            for mv in &full {
+                assert!( reduced.contains( &representative( &pos.snapshot, *mv ) ), "{:?} has no stand-in", mv );
+            }
+        }
+    }
+
+    #[test]
+    fn bucketed_moves_are_real_moves() {
+        for pos in random_positions() {
+            let full = gen_split_moves( &pos.snapshot, pos.to_move );
+            let bucketed = gen_bucketed_moves( &pos.snapshot, pos.to_move );
+            assert!( !bucketed.is_empty() );
+            for mv in &bucketed {
+                assert!( full.contains( mv ), "{:?} isn't a legal move", mv );
+            }
+        }
+    }
+
+    #[test]
+    fn amount_buckets_stay_in_range() {
+        assert_eq!( amount_buckets( 2 ), vec![ 1 ] );
+        assert_eq!( amount_buckets( 3 ), vec![ 1, 2 ] );
+        assert_eq!( amount_buckets( 20 ), vec![ 1, 2, 5, 10, 15, 18, 19 ] );
+        for power in 2..=u8::MAX {
+            let amounts = amount_buckets( power );
+            assert!( amounts.iter().all( |amount| (1..power).contains( amount ) ) );
+            assert!( amounts.windows( 2 ).all( |pair| pair[0] < pair[1] ) );
+        }
+    }
+}
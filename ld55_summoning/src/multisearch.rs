@@ -0,0 +1,297 @@
+// Search for games with three or four seats.
+//
+// Three classic ways of looking ahead when there is more than one opponent:
+//  - Max-n: every seat picks the move that is best for itself.
+//  - Paranoid: everyone else is assumed to be ganging up on us, which turns it
+//    back into a two player game so alpha-beta works.
+//  - Best-reply: like paranoid, but only the single most annoying opponent gets
+//    to move between our turns, so we can see further ahead.
+//
+// All of them work from the evaluator's score vector, turned into a "how far ahead
+// of everyone else" score per seat the same way the greedy bot weighs it.
+
+use std::sync::Arc;
+
+use web_time::Instant;
+
+use crate::evaluator::Evaluator;
+use crate::gamestate::{EvalWeights, Position, SplitMove};
+use crate::movegen::MoveGen;
+use crate::search::{sort_candidates, SearchLimits, SearchResult, StopFlag, WIN_SCORE};
+
+const INF : i32 = 1_500_000_000;
+
+const TIME_CHECK_NODES : u64 = 1024;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MultiStrategy {
+    MaxN,
+    Paranoid,
+    #[default]
+    BestReply,
+}
+
+impl MultiStrategy {
+    pub fn name( &self ) -> &'static str {
+        match self {
+            MultiStrategy::MaxN => "max-n",
+            MultiStrategy::Paranoid => "paranoid",
+            MultiStrategy::BestReply => "best-reply",
+        }
+    }
+
+    pub fn next( &self ) -> MultiStrategy {
+        match self {
+            MultiStrategy::MaxN => MultiStrategy::Paranoid,
+            MultiStrategy::Paranoid => MultiStrategy::BestReply,
+            MultiStrategy::BestReply => MultiStrategy::MaxN,
+        }
+    }
+
+    // The reverse of name
+    pub fn parse( text : &str ) -> Option<MultiStrategy> {
+        [ MultiStrategy::MaxN, MultiStrategy::Paranoid, MultiStrategy::BestReply ].into_iter()
+            .find( |strategy| strategy.name() == text.to_lowercase() )
+    }
+}
+
+// Each seat's score relative to the other seats in the game. Finished games are
+// scored by the final stack count, so a real lead beats any positional promise.
+pub fn relative_scores( pos : &Position, evaluator : &dyn Evaluator ) -> [i32; 4]
+{
+    let mut raw = [0; 4];
+    if pos.is_game_over() {
+        for (seat, score) in raw.iter_mut().enumerate() {
+            *score = pos.snapshot.calc_simple_score( seat as i32 ) * WIN_SCORE;
+        }
+    } else {
+        raw = evaluator.evaluate( &pos.snapshot );
+    }
+
+    let others = pos.seat_count() - 1;
+    let total : i32 = (0..4).filter( |s| pos.is_seated( *s ) ).map( |s| raw[s] ).sum();
+    let mut result = [0; 4];
+    for seat in 0..4 {
+        if pos.is_seated( seat ) {
+            // same as raw*(n-1) - sum(others), divided down so it stays in range
+            result[seat] = raw[seat] - (total - raw[seat]) / others.max( 1 );
+        }
+    }
+    result
+}
+
+pub struct MultiSearch {
+    pub strategy : MultiStrategy,
+    pub evaluator : Arc<dyn Evaluator>,
+    pub stop : StopFlag,
+    pub move_gen : MoveGen,
+    nodes : u64,
+    deadline : Option<Instant>,
+    aborted : bool,
+}
+
+impl Default for MultiSearch {
+    fn default() -> Self {
+        MultiSearch::new( MultiStrategy::default() )
+    }
+}
+
+impl MultiSearch {
+    pub fn new( strategy : MultiStrategy ) -> MultiSearch {
+        MultiSearch {
+            strategy,
+            evaluator : Arc::new( EvalWeights::default() ),
+            stop : StopFlag::default(),
+            move_gen : MoveGen::default(),
+            nodes : 0,
+            deadline : None,
+            aborted : false,
+        }
+    }
+
+    pub fn search( &mut self, pos : &Position, limits : SearchLimits ) -> SearchResult {
+        let moves = self.move_gen.moves( &pos.snapshot, pos.to_move );
+        self.search_moves( pos, moves, limits )
+    }
+
+    // Only consider the given moves at the root, so a particular move can be scored
+    pub fn search_moves( &mut self, pos : &Position, mut moves : Vec<SplitMove>, limits : SearchLimits ) -> SearchResult {
+        self.nodes = 0;
+        self.aborted = false;
+        self.deadline = limits.time_budget.map( |budget| Instant::now() + budget );
+
+        let mut result = SearchResult::default();
+        if moves.is_empty() {
+            return result;
+        }
+        result.best = Some( moves[0] );
+
+        let root = pos.to_move;
+        for depth in 1..=limits.max_depth.max( 1 ) {
+            if let Some(best) = result.best {
+                if let Some(i) = moves.iter().position( |m| *m == best ) {
+                    moves[..=i].rotate_right( 1 );
+                }
+            }
+
+            let mut alpha = -INF;
+            let mut best = None;
+            let mut candidates = Vec::new();
+            for mv in &moves {
+                let child = pos.play( *mv );
+                let value = match self.strategy {
+                    MultiStrategy::MaxN => self.maxn( &child, depth - 1 )[root],
+                    MultiStrategy::Paranoid => self.paranoid( &child, root, depth - 1, alpha, INF ),
+                    MultiStrategy::BestReply => {
+                        // Our move is done, now the opponents get their say
+                        let mut child = *pos;
+                        child.snapshot.apply_move( *mv );
+                        self.best_reply( &child, root, false, depth - 1, alpha, INF )
+                    }
+                };
+                if self.aborted {
+                    break;
+                }
+                candidates.push( (*mv, value) );
+                if value > alpha || best.is_none() {
+                    alpha = value;
+                    best = Some( *mv );
+                }
+            }
+
+            if self.aborted {
+                if best.is_some() {
+                    result.best = best;
+                    result.score = alpha;
+                    result.candidates = candidates;
+                }
+                break;
+            }
+
+            result.best = best;
+            result.score = alpha;
+            result.depth = depth;
+            result.candidates = candidates;
+        }
+
+        sort_candidates( &mut result.candidates );
+        result.nodes = self.nodes;
+        result
+    }
+
+    fn tick( &mut self ) -> bool {
+        self.nodes += 1;
+        if self.nodes & (TIME_CHECK_NODES - 1) == 0 {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    self.aborted = true;
+                }
+            }
+            if self.stop.is_stopped() {
+                self.aborted = true;
+            }
+        }
+        self.aborted
+    }
+
+    // Max-n has no useful pruning with these scores, every seat just maximizes its own entry
+    fn maxn( &mut self, pos : &Position, depth : u32 ) -> [i32; 4] {
+        if self.tick() {
+            return [0; 4];
+        }
+        if depth == 0 || pos.is_game_over() {
+            return relative_scores( pos, self.evaluator.as_ref() );
+        }
+
+        let mover = pos.to_move;
+        let mut best = [-INF; 4];
+        for mv in self.move_gen.moves( &pos.snapshot, mover ) {
+            let values = self.maxn( &pos.play( mv ), depth - 1 );
+            if self.aborted {
+                return [0; 4];
+            }
+            if values[mover] > best[mover] {
+                best = values;
+            }
+        }
+        best
+    }
+
+    // Plain alpha-beta where only `root` maximizes and every other seat minimizes root's score
+    fn paranoid( &mut self, pos : &Position, root : usize, depth : u32, mut alpha : i32, mut beta : i32 ) -> i32 {
+        if self.tick() {
+            return 0;
+        }
+        if depth == 0 || pos.is_game_over() {
+            return relative_scores( pos, self.evaluator.as_ref() )[root];
+        }
+
+        let maximizing = pos.to_move == root;
+        let mut best = if maximizing { -INF } else { INF };
+        for mv in self.move_gen.moves( &pos.snapshot, pos.to_move ) {
+            let value = self.paranoid( &pos.play( mv ), root, depth - 1, alpha, beta );
+            if self.aborted {
+                return 0;
+            }
+            if maximizing {
+                best = best.max( value );
+                alpha = alpha.max( value );
+            } else {
+                best = best.min( value );
+                beta = beta.min( value );
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    // Best-reply search: the layers alternate between us and a combined "opponent" layer
+    // where exactly one of the other seats gets to move, whichever hurts us the most.
+    fn best_reply( &mut self, pos : &Position, root : usize, our_turn : bool, depth : u32, mut alpha : i32, mut beta : i32 ) -> i32 {
+        if self.tick() {
+            return 0;
+        }
+        if depth == 0 || pos.is_game_over() {
+            return relative_scores( pos, self.evaluator.as_ref() )[root];
+        }
+
+        let movers : Vec<usize> = if our_turn {
+            vec![ root ]
+        } else {
+            (0..4).filter( |s| *s != root && pos.is_seated( *s ) ).collect()
+        };
+
+        let mut moves : Vec<SplitMove> = Vec::new();
+        for seat in movers {
+            moves.extend( self.move_gen.moves( &pos.snapshot, seat ) );
+        }
+
+        // Nobody on this side can move, hand it straight back
+        if moves.is_empty() {
+            return self.best_reply( pos, root, !our_turn, depth, alpha, beta );
+        }
+
+        let mut best = if our_turn { -INF } else { INF };
+        for mv in moves {
+            let mut child = *pos;
+            child.snapshot.apply_move( mv );
+            let value = self.best_reply( &child, root, !our_turn, depth - 1, alpha, beta );
+            if self.aborted {
+                return 0;
+            }
+            if our_turn {
+                best = best.max( value );
+                alpha = alpha.max( value );
+            } else {
+                best = best.min( value );
+                beta = beta.min( value );
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
@@ -0,0 +1,362 @@
+// Text protocol for engines that run as their own program, in the spirit of UCI.
+//
+// One command per line on the engine's stdin, one reply per line on its stdout:
+//
+//   to the engine                          from the engine
+//   engine                                 id name <name> (optional), then engineok
+//   isready                                readyok
+//   newgame <players> <layout>
+//   position <to move> <stacks>
+//   move <player> <move>
+//   go [movetime <ms>] [depth <n>]         bestmove <move>, or bestmove pass
+//   stop                                   (bestmove straight away)
+//   quit
+//
+// Players are numbered 1 to 4 like on screen, and <players> lists the ones in the
+// game with commas, e.g. "1,2,4". Hexes are named by column letter and row, "a0"
+// to "j9", and a move is "<from>-<to>/<amount>", e.g. "c4-c7/5". The layout is
+// all 100 hexes in index order, '.' for playable, '#' for blocked and '-' for off
+// the board. Stacks are "<hex>:<player>:<power>" joined with commas, or "-" for
+// an empty board.
+//
+// A game starts with newgame and a position, then every move from every player
+// comes in with move, including the engine's own. Players with no moves left are
+// skipped just like in the game. Engines may print "info <anything>" while they
+// think, and should ignore lines they don't understand. A move that isn't legal
+// for its player is ignored too.
+
+use crate::gamestate::{gen_split_moves, GameSnapshot, MapSpaceContents, Position, SplitMove, MAP_SZ};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stack {
+    pub ndx : i32,
+    pub player : u8,  // 1 to 4, same as MapSpace
+    pub power : u8,
+}
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    Engine,
+    IsReady,
+    NewGame { seats : u8, layout : Box<GameSnapshot> },  // layout has no stacks on it
+    Position { to_move : usize, stacks : Vec<Stack> },
+    Move { seat : usize, mv : SplitMove },
+    Go { movetime_ms : Option<u64>, depth : Option<u32> },
+    Stop,
+    Quit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    Id( String ),
+    EngineOk,
+    ReadyOk,
+    BestMove( Option<SplitMove> ),
+    Info( String ),
+}
+
+pub fn hex_name( ndx : i32 ) -> String {
+    let row = ndx / MAP_SZ as i32;
+    let col = ndx % MAP_SZ as i32;
+    format!( "{}{}", (b'a' + col as u8) as char, row )
+}
+
+pub fn parse_hex( text : &str ) -> Option<i32> {
+    let mut chars = text.chars();
+    let col = chars.next()? as i32 - 'a' as i32;
+    let row = chars.as_str().parse::<i32>().ok()?;
+    if (0..MAP_SZ as i32).contains( &col ) && (0..MAP_SZ as i32).contains( &row ) {
+        Some( row * MAP_SZ as i32 + col )
+    } else {
+        None
+    }
+}
+
+pub fn move_text( mv : SplitMove ) -> String {
+    format!( "{}-{}/{}", hex_name( mv.from ), hex_name( mv.to ), mv.amount )
+}
+
+// A move, or "pass" for a turn with no move
+pub fn turn_text( mv : Option<SplitMove> ) -> String {
+    mv.map_or( String::from( "pass" ), move_text )
+}
+
+pub fn parse_move( text : &str ) -> Option<SplitMove> {
+    let (hexes, amount) = text.split_once( '/' )?;
+    let (from, to) = hexes.split_once( '-' )?;
+    Some( SplitMove { from : parse_hex( from )?, to : parse_hex( to )?, amount : amount.parse().ok()? } )
+}
+
+pub fn layout_text( snapshot : &GameSnapshot ) -> String {
+    snapshot.map.spaces.iter().map( |space| match space.contents {
+        MapSpaceContents::Playable => '.',
+        MapSpaceContents::Blocked => '#',
+        MapSpaceContents::NotInMap => '-',
+    }).collect()
+}
+
+pub fn parse_layout( text : &str ) -> Option<GameSnapshot> {
+    if text.len() != MAP_SZ * MAP_SZ {
+        return None;
+    }
+    let mut snapshot = GameSnapshot::default();
+    for ((ndx, space), c) in snapshot.map.spaces.iter_mut().enumerate().zip( text.chars() ) {
+        space.ndx = ndx as i32;
+        space.contents = match c {
+            '.' => MapSpaceContents::Playable,
+            '#' => MapSpaceContents::Blocked,
+            '-' => MapSpaceContents::NotInMap,
+            _ => return None,
+        };
+    }
+    Some( snapshot )
+}
+
+pub fn stacks_of( snapshot : &GameSnapshot ) -> Vec<Stack> {
+    snapshot.map.spaces.iter()
+        .filter( |space| space.power > 0 )
+        .map( |space| Stack { ndx : space.ndx, player : space.player, power : space.power } )
+        .collect()
+}
+
+// Replaces whatever stacks were on the board
+pub fn set_stacks( snapshot : &mut GameSnapshot, stacks : &[Stack] ) {
+    for space in &mut snapshot.map {
+        space.player = 0;
+        space.power = 0;
+    }
+    for stack in stacks {
+        let space = &mut snapshot.map.spaces[stack.ndx as usize];
+        space.player = stack.player;
+        space.power = stack.power;
+    }
+    snapshot.update_scores();
+}
+
+// The position after a move that came in with `move`, or why it can't be played
+pub fn play_move( pos : &Position, seat : usize, mv : SplitMove ) -> Result<Position, String> {
+    if !gen_split_moves( &pos.snapshot, seat ).contains( &mv ) {
+        return Err( format!( "{} isn't a legal move for player {}", move_text( mv ), seat + 1 ) );
+    }
+    let mut pos = *pos;
+    pos.to_move = seat;
+    Ok( pos.play( mv ) )
+}
+
+fn stacks_text( stacks : &[Stack] ) -> String {
+    if stacks.is_empty() {
+        return String::from( "-" );
+    }
+    let parts : Vec<String> = stacks.iter().map( |s| format!( "{}:{}:{}", hex_name( s.ndx ), s.player, s.power ) ).collect();
+    parts.join( "," )
+}
+
+fn parse_stacks( text : &str ) -> Option<Vec<Stack>> {
+    if text == "-" {
+        return Some( Vec::new() );
+    }
+    text.split( ',' ).map( |part| {
+        let mut fields = part.split( ':' );
+        let ndx = parse_hex( fields.next()? )?;
+        let player = fields.next()?.parse().ok().filter( |p| (1..=4).contains( p ) )?;
+        let power = fields.next()?.parse().ok()?;
+        Some( Stack { ndx, player, power } )
+    }).collect()
+}
+
+// Players are 1 to 4 in the text, seats are 0 to 3
+fn parse_player( text : &str ) -> Option<usize> {
+    text.parse::<usize>().ok().filter( |p| (1..=4).contains( p ) ).map( |p| p - 1 )
+}
+
+impl Command {
+    pub fn to_text( &self ) -> String {
+        match self {
+            Command::Engine => String::from( "engine" ),
+            Command::IsReady => String::from( "isready" ),
+            Command::NewGame { seats, layout } => {
+                let players : Vec<String> = (0..4).filter( |s| seats & (1 << s) != 0 ).map( |s| (s + 1).to_string() ).collect();
+                format!( "newgame {} {}", players.join( "," ), layout_text( layout ) )
+            }
+            Command::Position { to_move, stacks } => format!( "position {} {}", to_move + 1, stacks_text( stacks ) ),
+            Command::Move { seat, mv } => format!( "move {} {}", seat + 1, move_text( *mv ) ),
+            Command::Go { movetime_ms, depth } => {
+                let mut text = String::from( "go" );
+                if let Some(ms) = movetime_ms {
+                    text += &format!( " movetime {}", ms );
+                }
+                if let Some(depth) = depth {
+                    text += &format!( " depth {}", depth );
+                }
+                text
+            }
+            Command::Stop => String::from( "stop" ),
+            Command::Quit => String::from( "quit" ),
+        }
+    }
+
+    pub fn parse( line : &str ) -> Option<Command> {
+        let mut words = line.split_whitespace();
+        let command = match words.next()? {
+            "engine" => Command::Engine,
+            "isready" => Command::IsReady,
+            "newgame" => {
+                let mut seats = 0;
+                for player in words.next()?.split( ',' ) {
+                    seats |= 1 << parse_player( player )?;
+                }
+                Command::NewGame { seats, layout : Box::new( parse_layout( words.next()? )? ) }
+            }
+            "position" => Command::Position {
+                to_move : parse_player( words.next()? )?,
+                stacks : parse_stacks( words.next()? )?,
+            },
+            "move" => Command::Move {
+                seat : parse_player( words.next()? )?,
+                mv : parse_move( words.next()? )?,
+            },
+            "go" => {
+                let (mut movetime_ms, mut depth) = (None, None);
+                while let Some(word) = words.next() {
+                    match word {
+                        "movetime" => movetime_ms = Some( words.next()?.parse().ok()? ),
+                        "depth" => depth = Some( words.next()?.parse().ok()? ),
+                        _ => {}
+                    }
+                }
+                Command::Go { movetime_ms, depth }
+            }
+            "stop" => Command::Stop,
+            "quit" => Command::Quit,
+            _ => return None,
+        };
+        Some( command )
+    }
+}
+
+impl Reply {
+    pub fn to_text( &self ) -> String {
+        match self {
+            Reply::Id(name) => format!( "id name {}", name ),
+            Reply::EngineOk => String::from( "engineok" ),
+            Reply::ReadyOk => String::from( "readyok" ),
+            Reply::BestMove(mv) => format!( "bestmove {}", turn_text( *mv ) ),
+            Reply::Info(text) => format!( "info {}", text ),
+        }
+    }
+
+    pub fn parse( line : &str ) -> Option<Reply> {
+        let line = line.trim();
+        let (word, rest) = line.split_once( ' ' ).unwrap_or( (line, "") );
+        let reply = match word {
+            "id" => Reply::Id( rest.strip_prefix( "name" )?.trim().to_string() ),
+            "engineok" => Reply::EngineOk,
+            "readyok" => Reply::ReadyOk,
+            "bestmove" if rest.trim() == "pass" => Reply::BestMove( None ),
+            "bestmove" => Reply::BestMove( Some( parse_move( rest.trim() )? ) ),
+            "info" => Reply::Info( rest.to_string() ),
+            _ => return None,
+        };
+        Some( reply )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::gamestate::generate_map;
+
+    #[test]
+    fn commands_survive_the_round_trip() {
+        let snapshot = generate_map( 0b1011, &mut StdRng::seed_from_u64( 7 ) );
+        let mut layout = snapshot;
+        set_stacks( &mut layout, &[] );
+        let mv = SplitMove { from : 23, to : 57, amount : 9 };
+
+        let commands = [
+            Command::Engine,
+            Command::IsReady,
+            Command::NewGame { seats : 0b1011, layout : Box::new( layout ) },
+            Command::Position { to_move : 3, stacks : stacks_of( &snapshot ) },
+            Command::Position { to_move : 0, stacks : Vec::new() },
+            Command::Move { seat : 1, mv },
+            Command::Go { movetime_ms : Some( 1500 ), depth : None },
+            Command::Go { movetime_ms : Some( 250 ), depth : Some( 6 ) },
+            Command::Go { movetime_ms : None, depth : None },
+            Command::Stop,
+            Command::Quit,
+        ];
+        for command in commands {
+            let text = command.to_text();
+            let parsed = Command::parse( &text ).unwrap_or_else( || panic!( "couldn't parse '{}'", text ) );
+            assert_eq!( parsed.to_text(), text );
+        }
+    }
+
+    #[test]
+    fn layouts_and_stacks_survive_the_round_trip() {
+        let snapshot = generate_map( 0b0011, &mut StdRng::seed_from_u64( 3 ) );
+        let Some(Command::NewGame { seats, layout }) = Command::parse( &format!( "newgame 1,2 {}", layout_text( &snapshot ) ) ) else {
+            panic!( "couldn't parse the layout" );
+        };
+        assert_eq!( seats, 0b0011 );
+
+        let text = Command::Position { to_move : 1, stacks : stacks_of( &snapshot ) }.to_text();
+        let Some(Command::Position { to_move, stacks }) = Command::parse( &text ) else {
+            panic!( "couldn't parse '{}'", text );
+        };
+        assert_eq!( to_move, 1 );
+
+        let mut rebuilt = *layout;
+        set_stacks( &mut rebuilt, &stacks );
+        assert_eq!( rebuilt.hash(), snapshot.hash() );
+        for (space, original) in rebuilt.map.spaces.iter().zip( snapshot.map.spaces.iter() ) {
+            assert_eq!( space.contents, original.contents );
+        }
+    }
+
+    #[test]
+    fn replies_survive_the_round_trip() {
+        let replies = [
+            Reply::Id( String::from( "ld55 hard (balanced)" ) ),
+            Reply::EngineOk,
+            Reply::ReadyOk,
+            Reply::BestMove( Some( SplitMove { from : 0, to : 99, amount : 1 } ) ),
+            Reply::BestMove( None ),
+            Reply::Info( String::from( "depth 4 nodes 1234 score -56" ) ),
+        ];
+        for reply in replies {
+            assert_eq!( Reply::parse( &reply.to_text() ), Some( reply ) );
+        }
+    }
+
+    #[test]
+    fn illegal_moves_are_refused() {
+        let Some(Command::NewGame { seats, layout }) = Command::parse( &format!( "newgame 1,2 {}", ".".repeat( 100 ) ) ) else {
+            panic!( "couldn't parse the layout" );
+        };
+        let mut pos = Position::new( *layout, 0, seats );
+        set_stacks( &mut pos.snapshot, &[ Stack { ndx : 0, player : 1, power : 4 }, Stack { ndx : 99, player : 2, power : 4 } ] );
+
+        // More than the stack has, someone else's stack, an empty hex, and not along a ray
+        for text in [ "move 1 a0-b0/5", "move 1 a0-b0/4", "move 2 a0-b0/1", "move 1 c3-d3/1", "move 1 a0-c5/1" ] {
+            let Some(Command::Move { seat, mv }) = Command::parse( text ) else { panic!( "couldn't parse '{}'", text ) };
+            assert!( play_move( &pos, seat, mv ).is_err(), "played '{}'", text );
+        }
+
+        let mv = gen_split_moves( &pos.snapshot, 1 )[0];
+        let after = play_move( &pos, 1, mv ).expect( "a legal move" );
+        assert_eq!( after.snapshot.calc_simple_score( 1 ), 2 );
+        assert_eq!( after.to_move, 0 );
+    }
+
+    #[test]
+    fn bad_lines_are_ignored() {
+        for line in [ "", "bestmove", "move 5 a1-a2/1", "move 1 a1-a2", "position 1 z9:1:1", "newgame 1,2 ...", "hello" ] {
+            assert!( Command::parse( line ).is_none() && Reply::parse( line ).is_none(), "parsed '{}'", line );
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Scoring at the end of each round, once every seat has had a turn.
+//
+// Each round every seat scores its stacks on the board, and the bonuses go to
+// whoever is out on their own in front: the biggest group of touching stacks,
+// and the most empty hexes left to split onto. The game is still won on stacks,
+// bonus points only settle ties.
+
+use crate::gamestate::{gen_split_moves, GameSnapshot};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bonus {
+    BiggestGroup,
+    MostRoom,
+}
+
+impl Bonus {
+    pub fn name( &self ) -> &'static str {
+        match self {
+            Bonus::BiggestGroup => "Biggest group",
+            Bonus::MostRoom => "Most room",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RoundScore {
+    pub stacks : [i32; 4],
+    pub bonuses : Vec<(usize, Bonus)>,  // with the seat that got each one
+}
+
+impl RoundScore {
+    // A point for each bonus
+    pub fn bonus_points( &self, seat : usize ) -> i32 {
+        self.bonuses.iter().filter( |(s, _)| *s == seat ).count() as i32
+    }
+}
+
+// Number of stacks in the seat's biggest group of stacks on neighbouring hexes
+pub fn biggest_group( snap : &GameSnapshot, seat : usize ) -> i32 {
+    let owner = (seat + 1) as u8;
+    let mut seen = [false; 100];
+    let mut biggest = 0;
+
+    for start in &snap.map {
+        if start.power == 0 || start.player != owner || seen[start.ndx as usize] {
+            continue;
+        }
+
+        let mut size = 0;
+        let mut open = vec![ start.ndx ];
+        seen[start.ndx as usize] = true;
+        while let Some(ndx) = open.pop() {
+            size += 1;
+            for nbr in snap.map.neighbors( ndx, true ) {
+                let space = snap.map.spaces[nbr as usize];
+                if space.power > 0 && space.player == owner && !seen[nbr as usize] {
+                    seen[nbr as usize] = true;
+                    open.push( nbr );
+                }
+            }
+        }
+        biggest = biggest.max( size );
+    }
+    biggest
+}
+
+// Empty hexes the seat could split onto next turn
+pub fn room( snap : &GameSnapshot, seat : usize ) -> i32 {
+    let mut reachable = [false; 100];
+    for mv in gen_split_moves( snap, seat ) {
+        reachable[mv.to as usize] = true;
+    }
+    reachable.iter().filter( |r| **r ).count() as i32
+}
+
+// The seat that is strictly ahead on some measure, if there is one
+fn sole_leader( seats : u8, measure : impl Fn( usize ) -> i32 ) -> Option<usize> {
+    let values : Vec<(usize, i32)> = (0..4).filter( |s| seats & (1 << s) != 0 ).map( |s| (s, measure( s )) ).collect();
+    let top = values.iter().map( |(_, v)| *v ).max()?;
+    let mut leaders = values.iter().filter( |(_, v)| *v == top );
+    match (leaders.next(), leaders.next()) {
+        (Some((seat, _)), None) if top > 0 => Some( *seat ),
+        _ => None,
+    }
+}
+
+pub fn score_round( snap : &GameSnapshot, seats : u8 ) -> RoundScore {
+    let mut result = RoundScore::default();
+    for seat in (0..4).filter( |s| seats & (1 << s) != 0 ) {
+        result.stacks[seat] = snap.calc_simple_score( seat as i32 );
+    }
+
+    if let Some(seat) = sole_leader( seats, |s| biggest_group( snap, s ) ) {
+        result.bonuses.push( (seat, Bonus::BiggestGroup) );
+    }
+    if let Some(seat) = sole_leader( seats, |s| room( snap, s ) ) {
+        result.bonuses.push( (seat, Bonus::MostRoom) );
+    }
+    result
+}
@@ -0,0 +1,417 @@
+// Alpha-beta search for two player games.
+//
+// Plain negamax with iterative deepening, a transposition table and some cheap
+// move ordering. The leaves are scored by the evaluator, finished games
+// are scored by the final stack count so a won game always beats a good looking one.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use web_time::{Duration, Instant};
+
+use crate::evaluator::Evaluator;
+use crate::gamestate::{EvalWeights, MapSpaceContents, Position, SplitMove};
+use crate::movegen::MoveGen;
+
+// Worth more than any difference evaluate_position can produce
+pub const WIN_SCORE : i32 = 20_000_000;
+const INF : i32 = 1_500_000_000;
+
+// How often (in nodes) to look at the clock, must be a power of two
+const TIME_CHECK_NODES : u64 = 1024;
+
+// How many root moves a SearchResult keeps for the debug overlay
+pub const MAX_CANDIDATES : usize = 8;
+
+#[derive(Copy, Clone, Debug)]
+pub struct SearchLimits {
+    pub max_depth : u32,
+    pub time_budget : Option<Duration>,
+}
+
+impl SearchLimits {
+    pub fn depth( max_depth : u32 ) -> SearchLimits {
+        SearchLimits { max_depth, time_budget : None }
+    }
+
+    pub fn time( budget : Duration ) -> SearchLimits {
+        SearchLimits { max_depth : 64, time_budget : Some( budget ) }
+    }
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits { max_depth : 8, time_budget : Some( Duration::from_millis( 1500 ) ) }
+    }
+}
+
+// Lets another thread tell a running search to wrap up and return its best move so far
+#[derive(Clone, Debug, Default)]
+pub struct StopFlag( Arc<AtomicBool> );
+
+impl StopFlag {
+    pub fn stop( &self ) {
+        self.0.store( true, Ordering::Relaxed );
+    }
+
+    pub fn reset( &self ) {
+        self.0.store( false, Ordering::Relaxed );
+    }
+
+    pub fn is_stopped( &self ) -> bool {
+        self.0.load( Ordering::Relaxed )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchResult {
+    pub best : Option<SplitMove>,
+    pub score : i32,
+    pub depth : u32,  // deepest fully completed iteration
+    pub nodes : u64,
+    pub proven : bool,  // solved right to the end, the score is the final margin
+    // The root moves it looked at with their scores, best first, for the debug overlay.
+    // The searches with pruning only prove the others are no better than their score.
+    pub candidates : Vec<(SplitMove, i32)>,
+}
+
+// Best first, and only as many as the overlay shows
+pub fn sort_candidates( candidates : &mut Vec<(SplitMove, i32)> ) {
+    candidates.sort_by_key( |(_, score)| std::cmp::Reverse( *score ) );
+    candidates.truncate( MAX_CANDIDATES );
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Copy, Clone)]
+struct TTEntry {
+    key : u64,
+    depth : u32,
+    value : i32,
+    bound : Bound,
+    best : Option<SplitMove>,
+}
+
+pub struct AlphaBeta {
+    pub stop : StopFlag,
+    pub move_gen : MoveGen,
+    evaluator : Arc<dyn Evaluator>,
+    table : Vec<Option<TTEntry>>,  // allocated on the first search
+    table_bits : u32,
+    nodes : u64,
+    deadline : Option<Instant>,
+    aborted : bool,
+}
+
+impl Default for AlphaBeta {
+    fn default() -> Self {
+        AlphaBeta::new( 18 )
+    }
+}
+
+impl AlphaBeta {
+    pub fn new( table_bits : u32 ) -> AlphaBeta {
+        AlphaBeta {
+            stop : StopFlag::default(),
+            move_gen : MoveGen::default(),
+            evaluator : Arc::new( EvalWeights::default() ),
+            table : Vec::new(),
+            table_bits,
+            nodes : 0,
+            deadline : None,
+            aborted : false,
+        }
+    }
+
+    pub fn clear( &mut self ) {
+        self.table.iter_mut().for_each( |e| *e = None );
+    }
+
+    // Stored scores are only good for the evaluator they were found with
+    pub fn set_evaluator( &mut self, evaluator : Arc<dyn Evaluator> ) {
+        if evaluator.fingerprint() != self.evaluator.fingerprint() {
+            self.clear();
+        }
+        self.evaluator = evaluator;
+    }
+
+    // Score from the point of view of the seat to move, against the other seat
+    pub fn evaluate( pos : &Position, evaluator : &dyn Evaluator ) -> i32 {
+        let me = pos.to_move;
+        let other = pos.next_seat( me );
+        if pos.is_game_over() {
+            let margin = pos.snapshot.calc_simple_score( me as i32 ) - pos.snapshot.calc_simple_score( other as i32 );
+            return margin * WIN_SCORE;
+        }
+
+        let evals = evaluator.evaluate( &pos.snapshot );
+        evals[me] - evals[other]
+    }
+
+    pub fn search( &mut self, pos : &Position, limits : SearchLimits ) -> SearchResult {
+        let moves = self.move_gen.moves( &pos.snapshot, pos.to_move );
+        self.search_moves( pos, moves, limits )
+    }
+
+    // Only consider the given moves at the root, so a particular move can be scored
+    pub fn search_moves( &mut self, pos : &Position, mut moves : Vec<SplitMove>, limits : SearchLimits ) -> SearchResult {
+        if self.table.is_empty() {
+            self.table = vec![ None; 1 << self.table_bits ];
+        }
+        self.nodes = 0;
+        self.aborted = false;
+        self.deadline = limits.time_budget.map( |budget| Instant::now() + budget );
+
+        let mut result = SearchResult::default();
+        if moves.is_empty() {
+            return result;
+        }
+        result.best = Some( moves[0] );
+
+        // Every move fills an empty hex, so no game can last longer than this
+        let empties = pos.snapshot.map.spaces.iter().filter( |s| s.contents == MapSpaceContents::Playable && s.power == 0 ).count() as u32;
+
+        for depth in 1..=limits.max_depth.max( 1 ) {
+            // Search the best move from the last iteration first
+            if let Some(best) = result.best {
+                if let Some(i) = moves.iter().position( |m| *m == best ) {
+                    moves[..=i].rotate_right( 1 );
+                }
+            }
+
+            let mut alpha = -INF;
+            let mut best = None;
+            let mut candidates = Vec::new();
+            for mv in &moves {
+                let child = pos.play( *mv );
+                let value = self.child_value( pos, &child, depth - 1, alpha, INF );
+                if self.aborted {
+                    break;
+                }
+                candidates.push( (*mv, value) );
+                if value > alpha || best.is_none() {
+                    alpha = value;
+                    best = Some( *mv );
+                }
+            }
+
+            // The previous best move is searched first, so anything a partial
+            // iteration finished is at least as trustworthy as the last one
+            if self.aborted {
+                if best.is_some() {
+                    result.best = best;
+                    result.score = alpha;
+                    result.candidates = candidates;
+                }
+                break;
+            }
+
+            result.best = best;
+            result.score = alpha;
+            result.depth = depth;
+            result.candidates = candidates;
+
+            // No point searching deeper once every line has played out. A win found
+            // sooner could still turn into a bigger one further down.
+            if depth >= empties {
+                break;
+            }
+        }
+
+        sort_candidates( &mut result.candidates );
+        result.nodes = self.nodes;
+        result
+    }
+
+    // Negamax value of `child` seen from the seat to move in `parent`. Passes mean the
+    // same seat can move twice in a row, so only flip the sign when the seat changes.
+    fn child_value( &mut self, parent : &Position, child : &Position, depth : u32, alpha : i32, beta : i32 ) -> i32 {
+        if child.to_move == parent.to_move {
+            self.negamax( child, depth, alpha, beta )
+        } else {
+            -self.negamax( child, depth, -beta, -alpha )
+        }
+    }
+
+    fn negamax( &mut self, pos : &Position, depth : u32, mut alpha : i32, beta : i32 ) -> i32 {
+        self.nodes += 1;
+        if self.nodes & (TIME_CHECK_NODES - 1) == 0 {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    self.aborted = true;
+                }
+            }
+            if self.stop.is_stopped() {
+                self.aborted = true;
+            }
+        }
+        if self.aborted {
+            return 0;
+        }
+
+        if depth == 0 || pos.is_game_over() {
+            return AlphaBeta::evaluate( pos, self.evaluator.as_ref() );
+        }
+
+        let key = pos.hash();
+        let slot = (key as usize) & (self.table.len() - 1);
+        let mut tt_move = None;
+        if let Some(entry) = self.table[slot] {
+            if entry.key == key {
+                tt_move = entry.best;
+                if entry.depth >= depth {
+                    match entry.bound {
+                        Bound::Exact => return entry.value,
+                        Bound::Lower if entry.value >= beta => return entry.value,
+                        Bound::Upper if entry.value <= alpha => return entry.value,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let moves = self.ordered_moves( pos, depth, tt_move );
+
+        let alpha_orig = alpha;
+        let mut best_value = -INF;
+        let mut best_move = None;
+        for mv in moves {
+            let child = pos.play( mv );
+            let value = self.child_value( pos, &child, depth - 1, alpha, beta );
+            if self.aborted {
+                return 0;
+            }
+            if value > best_value {
+                best_value = value;
+                best_move = Some( mv );
+            }
+            alpha = alpha.max( value );
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best_value <= alpha_orig {
+            Bound::Upper
+        } else if best_value >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+
+        // Always replace, deeper entries from older iterations are less useful anyway
+        self.table[slot] = Some( TTEntry { key, depth, value : best_value, bound, best : best_move } );
+
+        best_value
+    }
+
+    // Transposition table move first, then the rest by how good they look right away.
+    // Close to the leaves the static sort costs more than it saves.
+    fn ordered_moves( &self, pos : &Position, depth : u32, tt_move : Option<SplitMove> ) -> Vec<SplitMove> {
+        let mut moves = self.move_gen.moves( &pos.snapshot, pos.to_move );
+
+        if depth >= 2 {
+            let mut scored : Vec<(i32, SplitMove)> = moves.iter().map( |mv| {
+                let mut snap = pos.snapshot;
+                snap.apply_move( *mv );
+                let evals = self.evaluator.evaluate( &snap );
+                let other = pos.next_seat( pos.to_move );
+                (evals[pos.to_move] - evals[other], *mv)
+            }).collect();
+            scored.sort_by_key( |(value, _)| std::cmp::Reverse( *value ) );
+            moves = scored.into_iter().map( |(_, mv)| mv ).collect();
+        }
+
+        if let Some(tt_move) = tt_move {
+            if let Some(i) = moves.iter().position( |m| *m == tt_move ) {
+                moves[..=i].rotate_right( 1 );
+            }
+        }
+
+        moves
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::gamestate::{gen_split_moves, generate_map};
+
+    fn empty_count( pos : &Position ) -> usize {
+        pos.snapshot.map.spaces.iter().filter( |s| s.contents == MapSpaceContents::Playable && s.power == 0 ).count()
+    }
+
+    // Random two player games, stopped with only a few empty hexes left
+    fn small_endgames( count : u64 ) -> Vec<Position> {
+        (0..count).filter_map( |seed| {
+            let mut rng = StdRng::seed_from_u64( seed );
+            let mut pos = Position::new( generate_map( 0b0011, &mut rng ), 3, 0b0011 );
+            pos.advance();
+            while !pos.is_game_over() && empty_count( &pos ) > 9 {
+                pos = pos.play( *gen_split_moves( &pos.snapshot, pos.to_move ).choose( &mut rng ).unwrap() );
+            }
+            (!pos.is_game_over()).then_some( pos )
+        }).collect()
+    }
+
+    // Final stack margin for the seat to move with perfect play, by trying everything
+    fn exact_margin( pos : &Position, memo : &mut HashMap<u64, i32> ) -> i32 {
+        let me = pos.to_move;
+        if pos.is_game_over() {
+            let other = pos.next_seat( me );
+            return pos.snapshot.calc_simple_score( me as i32 ) - pos.snapshot.calc_simple_score( other as i32 );
+        }
+        if let Some(margin) = memo.get( &pos.hash() ) {
+            return *margin;
+        }
+        let best = gen_split_moves( &pos.snapshot, me ).into_iter().map( |mv| {
+            let child = pos.play( mv );
+            let margin = exact_margin( &child, memo );
+            if child.to_move == me { margin } else { -margin }
+        }).max().unwrap();
+        memo.insert( pos.hash(), best );
+        best
+    }
+
+    #[test]
+    fn full_depth_search_finds_the_exact_margin() {
+        let endgames = small_endgames( 16 );
+        assert!( endgames.len() >= 10 );
+        let mut kept_deepening = 0;
+        for pos in endgames {
+            let exact = exact_margin( &pos, &mut HashMap::new() );
+            let result = AlphaBeta::new( 16 ).search( &pos, SearchLimits::depth( 100 ) );
+            assert_eq!( result.score, exact * WIN_SCORE );
+
+            // Positions where a shallower search already sees a win, just not the best one
+            let shallow_wins = (1..empty_count( &pos ) as u32).any( |depth| {
+                let score = AlphaBeta::new( 16 ).search( &pos, SearchLimits::depth( depth ) ).score;
+                score >= WIN_SCORE && score != exact * WIN_SCORE
+            });
+            if shallow_wins {
+                kept_deepening += 1;
+            }
+        }
+        assert!( kept_deepening > 0, "no position needed deepening past the first win" );
+    }
+
+    #[test]
+    fn searches_no_deeper_than_the_game_can_go() {
+        for pos in small_endgames( 4 ) {
+            let result = AlphaBeta::new( 16 ).search( &pos, SearchLimits::depth( 100 ) );
+            assert!( result.depth as usize <= empty_count( &pos ) );
+            assert!( result.best.is_some() );
+        }
+    }
+}